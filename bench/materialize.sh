@@ -0,0 +1,37 @@
+#!/usr/bin/env bash
+
+# Compares cold builds of the generated benchmark workspace (see gen.rb) with
+# every materialization strategy, and writes the results to
+# materialize-builds.md. See materialize.md for the cost of materializing
+# outputs alone.
+#
+# Usage: ./materialize.sh [path/to/zap]
+#
+# Requires hyperfine: https://github.com/sharkdp/hyperfine
+
+set -euo pipefail
+
+ZAP=${1:-zap}
+BENCH_DIR=$(cd "$(dirname "$0")" && pwd)
+ZAP_HOME=$(mktemp -d)
+USER_ROOT="${ZAP_HOME}/cache/_user_bench"
+
+cd "${BENCH_DIR}"
+if [ ! -d test_project ]; then
+  ruby gen.rb
+fi
+cd test_project
+
+# Warm up once so the toolchain is downloaded and built before measuring.
+"${ZAP}" --zap-home "${ZAP_HOME}" --user bench build > /dev/null
+
+# Only the build cache and the workspace outputs are cleared between runs,
+# so every target is rebuilt but the toolchain is kept.
+hyperfine \
+  --warmup 1 \
+  --parameter-list strategy copy,hardlink,symlink,reflink \
+  --prepare "rm -rf ${USER_ROOT}/cache/manifests ${USER_ROOT}/cache/blobs .zap/outputs .zap/sandbox" \
+  --export-markdown "${BENCH_DIR}/materialize-builds.md" \
+  "${ZAP} --zap-home ${ZAP_HOME} --user bench --materialize {strategy} build"
+
+rm -rf "${ZAP_HOME}"
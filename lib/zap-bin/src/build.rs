@@ -1,61 +1,290 @@
+use super::TerminalProgress;
 use log::*;
-use std::io;
-use std::io::Write;
 use structopt::StructOpt;
 use zap_build_engine::*;
 use zap_core::*;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(StructOpt, Debug, Clone)]
 #[structopt(
     name = "build",
     setting = structopt::clap::AppSettings::ColoredHelp,
+    setting = structopt::clap::AppSettings::AllowLeadingHyphen,
     about = "Build a target in this Workspace",
 )]
 pub struct BuildGoal {
     #[structopt(
-        help = r"The target to build.
+        help = r"The targets to build.
 
 A path to a directory with a zap file, followed by a colon
 and the name of the label to be built.
 
 Example: //my/library:lib
 
-Use //... to build the entire project.
+Use //my/library:all to build every target in a package,
+//my/library/... to also build the packages below it, and
+//... to build the entire project.
+
+Several targets are built together, in a single build. Prefix
+a target with - to leave it out:
+
+Example: //my/... -//my/library/tests:all
 ",
         default_value = "//..."
     )]
-    target: String,
+    targets: Vec<String>,
+
+    #[structopt(
+        short = "j",
+        long = "jobs",
+        help = r"The maximum number of targets to build concurrently.
+
+Defaults to the number of available CPUs.
+"
+    )]
+    jobs: Option<usize>,
+
+    #[structopt(
+        short = "k",
+        long = "keep-going",
+        help = r"Keep building all the targets that don't depend on a failed one.
+"
+    )]
+    keep_going: bool,
+
+    #[structopt(
+        long = "output",
+        default_value = "human",
+        possible_values = &["human", "json"],
+        help = r"How to report the outcome of every target in the build.
+
+Use json for machine-readable output.
+"
+    )]
+    output: OutputFormat,
+
+    #[structopt(
+        long = "profile",
+        help = r"Write a profile of the build to this file.
+
+The profile is a Chrome Trace Event file, that can be opened in
+chrome://tracing or https://ui.perfetto.dev
+"
+    )]
+    profile: Option<PathBuf>,
+
+    #[structopt(
+        long = "show-warnings",
+        help = r"Print the output of every action of the targets that were built,
+including the ones that were found in the cache.
+
+Logs are local to this workspace, so targets fetched from the remote cache
+have none to print.
+
+Use `zap logs <target>` to see the output of a single target.
+"
+    )]
+    show_warnings: bool,
+
+    #[structopt(
+        long = "strict",
+        help = r"Fail actions that read files from the workspace that were not declared
+as srcs of their target, or outputs of its dependencies.
+
+Only supported on Linux. Can also be enabled with `strict = true` in the
+[actions] section of your Workspace.toml
+
+Only files opened through open, openat or openat2 are checked: files that are
+only executed, stat-ed, read as links or mapped into memory are not reported.
+"
+    )]
+    strict: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> Result<OutputFormat, anyhow::Error> {
+        match format {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow::anyhow!("Unknown output format: {:?}", format)),
+        }
+    }
 }
 
 impl BuildGoal {
     pub fn all() -> BuildGoal {
         BuildGoal {
-            target: "//...".to_string(),
+            targets: vec!["//...".to_string()],
+            jobs: None,
+            keep_going: false,
+            output: OutputFormat::Human,
+            profile: None,
+            show_warnings: false,
+            strict: false,
         }
     }
 
+    /// Whether this goal prints machine-readable output, that should not be
+    /// mixed with anything else.
+    pub fn is_machine_readable(&self) -> bool {
+        self.output == OutputFormat::Json
+    }
+
     pub async fn run(self, config: ZapConfig) -> Result<(), anyhow::Error> {
-        let target: Label = self.target.into();
+        let patterns = self
+            .targets
+            .iter()
+            .map(|target| TargetPattern::parse(target))
+            .collect::<Result<Vec<TargetPattern>, LabelError>>()?;
         debug!("Host: {}", guess_host_triple::guess_host_triple().unwrap());
-        debug!("Target: {}", &target.to_string());
+        debug!("Targets: {:?}", &self.targets);
+
+        let profiler = match self.profile {
+            Some(_) => Profiler::enabled(),
+            None => Profiler::default(),
+        };
+        let config = config.with_profiler(profiler.clone());
 
         let mut zap = ZapWorker::new(config)?;
         zap.load(&PathBuf::from(&".")).await?;
         zap.build_dep_graph()?;
+        let targets = zap.dep_graph.find_matching(&patterns)?;
+
+        if self.strict {
+            let policy = ExecPolicy {
+                strict: true,
+                ..zap.workspace.exec_policy().clone()
+            };
+            zap.workspace = zap.workspace.clone().with_exec_policy(policy);
+        }
+
+        let jobs = self.jobs.unwrap_or_else(num_cpus::get);
+        debug!("Jobs: {}", jobs);
+
+        let name = match patterns.as_slice() {
+            [TargetPattern::Recursive(path)] if path.as_os_str().is_empty() => {
+                "workspace".to_string()
+            }
+            [TargetPattern::Target(target)] => target.to_string(),
+            _ => format!("{} ({} targets)", self.targets.join(" "), targets.len()),
+        };
+
+        let logs_root = zap.workspace.logs_root();
+
+        let mut runner = BuildRunner::new(zap)
+            .with_jobs(jobs)
+            .with_keep_going(self.keep_going);
 
-        let mut runner = BuildRunner::new(zap);
+        if self.output == OutputFormat::Human {
+            runner = runner.with_progress(TerminalProgress::new(name));
+        }
 
-        let name = if target.is_all() {
-            "workspace".to_string()
-        } else {
-            target.to_string()
+        let result = {
+            let _span = profiler.span("build", "build");
+            runner.execute(&targets).map(|_| ())
         };
 
-        print!("🔨 Building {}...", name);
-        io::stdout().flush().unwrap();
+        // NOTE: the profile is written even if the build failed, since that
+        // is when it is most useful.
+        if let Some(path) = &self.profile {
+            profiler.write(path)?;
+        }
+
+        let report = runner.report();
+        match self.output {
+            OutputFormat::Human if self.show_warnings => {
+                BuildGoal::print_logs(&report, &logs_root);
+                BuildGoal::print_report(&report, self.keep_going)
+            }
+            OutputFormat::Human => BuildGoal::print_report(&report, self.keep_going),
+            OutputFormat::Json => println!("{}", report.to_json()),
+        }
+
+        result
+    }
+
+    fn print_report(report: &BuildReport, list_succeeded: bool) {
+        let succeeded = report.with_status(&[ComputeStatus::Succeeded, ComputeStatus::CacheHit]);
+        let cached = report.with_status(&[ComputeStatus::CacheHit]);
+        let skipped = report.with_status(&[ComputeStatus::Skipped]);
+        let failed = report.with_status(&[ComputeStatus::Failed]);
 
-        runner.execute(&target).map(|_| ())
+        println!();
+        println!(
+            "✅ Succeeded ({}, {} from cache){}",
+            succeeded.len(),
+            cached.len(),
+            if list_succeeded && !succeeded.is_empty() {
+                ":"
+            } else {
+                ""
+            }
+        );
+        if list_succeeded {
+            for target in &succeeded {
+                println!("    {}", target.label.to_string());
+            }
+        }
+        for (icon, name, targets) in &[("⏭ ", "Skipped", &skipped), ("❌", "Failed", &failed)] {
+            if targets.is_empty() {
+                continue;
+            }
+            println!("{} {} ({}):", icon, name, targets.len());
+            for target in targets.iter() {
+                println!("    {}", target.label.to_string());
+            }
+        }
+
+        let mut built = report.with_status(&[ComputeStatus::Succeeded, ComputeStatus::Failed]);
+        built.sort_by_key(|target| std::cmp::Reverse(target.duration));
+        if !built.is_empty() {
+            println!("🐢 Slowest targets:");
+            for target in built.iter().take(5) {
+                println!(
+                    "    {:>8}ms  {} ({} actions)",
+                    target.duration.as_millis(),
+                    target.label.to_string(),
+                    target.actions
+                );
+            }
+        }
+    }
+
+    /// Print the output of the actions of every target that succeeded, either
+    /// because it was built or because it was found in the cache.
+    fn print_logs(report: &BuildReport, logs_root: &Path) {
+        let succeeded = report.with_status(&[ComputeStatus::Succeeded, ComputeStatus::CacheHit]);
+        for target in succeeded {
+            let hash = match &target.hash {
+                Some(hash) => hash,
+                None => continue,
+            };
+            let logs = match ActionLogs::new(logs_root, hash).read() {
+                Ok(logs) => logs,
+                Err(err) => {
+                    warn!("{:?}", err);
+                    continue;
+                }
+            };
+            let output: String = logs.into_iter().map(|(_, log)| log).collect();
+            if output.trim().is_empty() {
+                continue;
+            }
+
+            println!();
+            println!("⚠️  {}:", target.label.to_string());
+            for line in output.lines() {
+                println!("    {}", line);
+            }
+        }
     }
 }
@@ -1,6 +1,8 @@
 use anyhow::Context;
+use std::collections::HashSet;
 use std::path::PathBuf;
 use structopt::StructOpt;
+use zap_build_engine::*;
 use zap_core::*;
 
 #[derive(StructOpt, Debug, Clone)]
@@ -27,6 +29,55 @@ Must be a single target.
 ")]
         target: String,
     },
+
+    #[structopt(help = r"Remove every cache entry that is not reachable from the
+build graph of this Workspace, every output that is no longer used, and the
+logs of the targets whose entries were removed.
+
+The cache is shared by all workspaces, so entries that other workspaces have
+built or used are kept, unless --all-workspaces is passed. Entries left over
+from older versions of the cache are always removed.
+")]
+    Gc {
+        #[structopt(
+            long = "dry-run",
+            help = "Only report what would be removed, and how much space would be freed"
+        )]
+        dry_run: bool,
+
+        #[structopt(
+            long = "all-workspaces",
+            help = "Also remove entries used by other workspaces that are not reachable from this one"
+        )]
+        all_workspaces: bool,
+
+        #[structopt(
+            long = "keep",
+            default_value = "0",
+            help = "The number of most recent entries to keep for every target, even if unreachable"
+        )]
+        keep: usize,
+    },
+
+    #[structopt(help = r"Show how much space the cache is using, its hit rate,
+and its largest entries.
+")]
+    Stats {
+        #[structopt(
+            long = "top",
+            default_value = "10",
+            help = "The number of largest entries to show"
+        )]
+        top: usize,
+    },
+
+    #[structopt(help = r"Check every entry in the cache against the digests of its
+outputs, reporting missing or modified outputs.
+")]
+    Verify {
+        #[structopt(long = "repair", help = "Remove the corrupted entries from the cache")]
+        repair: bool,
+    },
 }
 
 impl CacheGoal {
@@ -39,11 +90,11 @@ impl CacheGoal {
                     .context("Could not remove entire Zap cache")
             }
             CacheGoal::Clear { target } => {
-                let target: Label = target.into();
+                let target = Label::parse(&target)?;
                 let mut zap = ZapWorker::new(config)?;
                 zap.load(&PathBuf::from(&".")).await?;
                 zap.build_dep_graph()?;
-                let dep_graph = &mut zap.dep_graph.scoped(&target)?.seal(
+                let dep_graph = &mut zap.dep_graph.scoped(std::slice::from_ref(&target))?.seal(
                     &zap.action_map,
                     &zap.output_map,
                     &mut zap.bs_ctx,
@@ -54,13 +105,159 @@ impl CacheGoal {
                     target.to_string()
                 ))?;
 
-                let cache_entry = zap.config.cache_root.join(node.hash());
+                BuildCache::new(&zap.config)
+                    .remove(&node.hash())
+                    .context(format!(
+                        "Could not remove cached target: {}",
+                        target.to_string()
+                    ))
+            }
+            CacheGoal::Gc {
+                dry_run,
+                all_workspaces,
+                keep,
+            } => {
+                let mut zap = ZapWorker::new(config)?;
+                zap.load(&PathBuf::from(&".")).await?;
+                zap.build_dep_graph()?;
+                let dep_graph =
+                    &mut zap
+                        .dep_graph
+                        .seal(&zap.action_map, &zap.output_map, &mut zap.bs_ctx)?;
 
-                std::fs::remove_dir_all(&cache_entry).context(format!(
-                    "Could not remove cached target: {}",
-                    target.to_string()
-                ))
+                let live: HashSet<String> = dep_graph.targets().iter().map(|t| t.hash()).collect();
+
+                let build_cache = BuildCache::new(&zap.config);
+                let build_cache = if all_workspaces {
+                    build_cache
+                } else {
+                    build_cache.with_workspace(zap.workspace.root())
+                };
+                let report = build_cache.gc(&live, keep, dry_run)?;
+
+                // NOTE: the logs of a target are kept for as long as its cache
+                // entry is, so `zap logs` still works on everything that is
+                // cached.
+                let removed: HashSet<&String> =
+                    report.removed_entries.iter().map(|e| &e.hash).collect();
+                let mut kept = live.clone();
+                kept.extend(
+                    build_cache
+                        .entries()?
+                        .into_iter()
+                        .map(|e| e.hash)
+                        .filter(|hash| !removed.contains(hash)),
+                );
+                let removed_logs = ActionLogs::prune(&zap.workspace.logs_root(), &kept, dry_run)?;
+
+                for entry in &report.removed_entries {
+                    println!(
+                        "{} {} ({})",
+                        if dry_run { "Would remove" } else { "Removed" },
+                        entry.manifest.label().unwrap_or("<unknown>"),
+                        entry.hash
+                    );
+                }
+
+                println!(
+                    "🧹 {} {} entries, {} legacy entries, {} outputs and {} logs, {} {}. Kept {} entries ({} used by other workspaces).",
+                    if dry_run { "Would remove" } else { "Removed" },
+                    report.removed_entries.len(),
+                    report.removed_legacy_entries,
+                    report.removed_blobs,
+                    removed_logs,
+                    if dry_run { "reclaiming" } else { "reclaimed" },
+                    human_bytes(report.reclaimable_bytes),
+                    report.kept_entries,
+                    report.shared_entries
+                );
+
+                Ok(())
+            }
+            CacheGoal::Stats { top } => {
+                let stats = BuildCache::new(&config).stats()?;
+
+                println!("Cache: {:?}", config.cache_root);
+                println!(
+                    "Usage: {}{} in {} entries and {} outputs",
+                    human_bytes(stats.total_bytes),
+                    match config.max_cache_size {
+                        Some(max) => format!(" of {}", human_bytes(max)),
+                        None => "".to_string(),
+                    },
+                    stats.entries,
+                    stats.blobs
+                );
+                match stats.hit_rate() {
+                    Some(rate) => println!(
+                        "Hit rate: {:.1}% ({} hits, {} misses)",
+                        rate * 100.0,
+                        stats.hits,
+                        stats.misses
+                    ),
+                    None => println!("Hit rate: no builds yet"),
+                }
+
+                if !stats.largest_entries.is_empty() {
+                    println!("Largest entries:");
+                    for (entry, size) in stats.largest_entries.iter().take(top) {
+                        println!(
+                            "  {:>9}  {} ({})",
+                            human_bytes(*size),
+                            entry.manifest.label().unwrap_or("<unknown>"),
+                            entry.hash
+                        );
+                    }
+                }
+
+                Ok(())
+            }
+            CacheGoal::Verify { repair } => {
+                let build_cache = BuildCache::new(&config);
+                let corrupted = build_cache.verify()?;
+
+                for (entry, corruptions) in &corrupted {
+                    println!(
+                        "{} ({})",
+                        entry.manifest.label().unwrap_or("<unknown>"),
+                        entry.hash
+                    );
+                    for corruption in corruptions {
+                        println!("  {}", corruption);
+                    }
+                    if repair {
+                        build_cache.discard(&entry.hash, corruptions)?;
+                    }
+                }
+
+                if corrupted.is_empty() {
+                    println!("✅ All cache entries are valid.");
+                    Ok(())
+                } else if repair {
+                    println!("🧹 Removed {} corrupted cache entries.", corrupted.len());
+                    Ok(())
+                } else {
+                    Err(anyhow::anyhow!(
+                        "Found {} corrupted cache entries. Run `zap cache verify --repair` to remove them.",
+                        corrupted.len()
+                    ))
+                }
             }
         }
     }
 }
+
+fn human_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}{}", bytes, units[unit])
+    } else {
+        format!("{:.1}{}", size, units[unit])
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::*;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 use zap_core::*;
 
@@ -24,6 +25,22 @@ enum Action {
     ")]
         target: String,
     },
+
+    #[structopt(
+        name = "critical-path",
+        about = "Print the chain of dependencies that takes the longest to build"
+    )]
+    CriticalPath {
+        #[structopt(
+            help = r"The target to find the critical path of.
+
+Durations are taken from the last build of every target, so
+targets that have never been built are assumed to take no time.
+",
+            default_value = "//..."
+        )]
+        target: String,
+    },
 }
 
 impl DepGraphGoal {
@@ -34,12 +51,13 @@ impl DepGraphGoal {
 
         match self.cmd {
             Action::Print { ref target } => self.print(&target, &mut zap),
+            Action::CriticalPath { ref target } => self.critical_path(target, &mut zap),
         }
     }
 
     fn print(&self, target: &str, zap: &mut ZapWorker) -> Result<(), anyhow::Error> {
-        let label: Label = target.into();
-        let dep_graph = &mut zap.dep_graph.scoped(&label)?.seal(
+        let label = Label::parse(target)?;
+        let dep_graph = &mut zap.dep_graph.scoped(std::slice::from_ref(&label))?.seal(
             &zap.action_map,
             &zap.output_map,
             &mut zap.bs_ctx,
@@ -49,4 +67,59 @@ impl DepGraphGoal {
 
         Ok(())
     }
+
+    fn critical_path(&self, target: &str, zap: &mut ZapWorker) -> Result<(), anyhow::Error> {
+        let label = Label::parse(target)?;
+        let timings = BuildTimings::read(&zap.workspace.timings_path())?;
+        let path = zap
+            .dep_graph
+            .scoped(std::slice::from_ref(&label))?
+            .critical_path(&timings);
+
+        let total: Duration = path.iter().flat_map(|(_, duration)| *duration).sum();
+        println!(
+            "Critical path for {} takes {:.2}s:",
+            label.to_string(),
+            total.as_secs_f64()
+        );
+        println!();
+        println!("{:>10} {:>10}  target", "total", "self");
+
+        let mut elapsed = Duration::from_secs(0);
+        let mut never_built = 0;
+        for (label, duration) in &path {
+            let own = match duration {
+                Some(duration) => {
+                    elapsed += *duration;
+                    format!("{:.2}s", duration.as_secs_f64())
+                }
+                None => {
+                    never_built += 1;
+                    "?".to_string()
+                }
+            };
+            println!(
+                "{:>10} {:>10}  {}",
+                format!("{:.2}s", elapsed.as_secs_f64()),
+                own,
+                label.to_string()
+            );
+        }
+
+        if never_built > 0 {
+            println!();
+            println!(
+                "{} {} never been built, build {} to get accurate timings.",
+                never_built,
+                if never_built == 1 {
+                    "target on this path has"
+                } else {
+                    "targets on this path have"
+                },
+                label.to_string()
+            );
+        }
+
+        Ok(())
+    }
 }
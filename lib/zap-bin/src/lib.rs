@@ -1,6 +1,8 @@
 pub mod build;
 pub mod cache;
 pub mod depgraph;
+pub mod logs;
+pub mod progress;
 pub mod rules;
 pub mod target;
 pub mod toolchain;
@@ -9,6 +11,8 @@ pub mod workspace;
 pub use build::*;
 pub use cache::*;
 pub use depgraph::*;
+pub use logs::*;
+pub use progress::*;
 pub use rules::*;
 pub use target::*;
 pub use toolchain::*;
@@ -0,0 +1,45 @@
+use anyhow::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use zap_core::*;
+
+#[derive(StructOpt, Debug, Clone)]
+#[structopt(
+    name = "logs",
+    setting = structopt::clap::AppSettings::ColoredHelp,
+    about = "Print the output of the last build of a target"
+)]
+pub struct LogsGoal {
+    #[structopt(help = r"The target to print the logs of.
+
+Example: //my/library:lib
+")]
+    target: String,
+}
+
+impl LogsGoal {
+    pub async fn run(self, config: ZapConfig) -> Result<(), anyhow::Error> {
+        let mut zap = ZapWorker::new(config)?;
+        zap.scan(&PathBuf::from(&"."))?;
+
+        let label = Label::parse(&self.target)?;
+        let logs = ActionLogs::latest(&zap.workspace.logs_root(), &label)?.context(format!(
+            "Could not find any logs for {}, has it been built yet?",
+            label.to_string()
+        ))?;
+
+        let logs = logs.read()?;
+        if logs.is_empty() {
+            println!("{} did not run any commands.", label.to_string());
+        }
+        for (n, log) in logs {
+            println!("==> Action #{}", n);
+            print!("{}", log);
+            if !log.is_empty() && !log.ends_with('\n') {
+                println!();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -31,6 +31,33 @@ struct Zap {
         help = "the root directory for the Zap global configuration"
     )]
     zap_home: Option<String>,
+
+    #[structopt(
+        long = "remote-cache",
+        help = "the URL of an HTTP remote cache to fetch build outputs from"
+    )]
+    remote_cache: Option<String>,
+
+    #[structopt(
+        long = "remote-cache-upload",
+        help = "upload build outputs to the remote cache"
+    )]
+    remote_cache_upload: bool,
+
+    #[structopt(
+        long = "max-cache-size",
+        parse(try_from_str = parse_size),
+        help = "the maximum size of the cache, in bytes or with a K, M, G, or T suffix. Least recently used entries will be evicted after every build"
+    )]
+    max_cache_size: Option<u64>,
+
+    #[structopt(
+        long = "materialize",
+        default_value = "copy",
+        possible_values = &["copy", "hardlink", "symlink", "reflink"],
+        help = "how files are put in place in sandboxes, outputs, and the cache. Falls back to copying when the strategy is not supported"
+    )]
+    materialize: MaterializationStrategy,
 }
 
 impl Zap {
@@ -58,13 +85,14 @@ impl Zap {
         };
 
         let cmd = self.cmd.unwrap_or_else(|| Goal::Build(BuildGoal::all()));
+        let quiet = self.quiet || cmd.is_machine_readable();
         match cmd.run(config).await {
             Ok(()) => (),
             Err(err) => error!("{:?}", &err),
         };
 
         let t1 = t0.elapsed().as_millis();
-        if !self.quiet {
+        if !quiet {
             println!("\x1B[1000D\x1B[K\r⚡ done in {}ms", t1);
         }
     }
@@ -73,7 +101,12 @@ impl Zap {
 impl TryInto<ZapConfig> for Zap {
     type Error = anyhow::Error;
     fn try_into(self) -> Result<ZapConfig, anyhow::Error> {
-        ZapConfig::new(self.zap_home.clone(), self.user.clone())
+        ZapConfig::new(self.zap_home.clone(), self.user.clone()).map(|config| {
+            config
+                .with_remote_cache(self.remote_cache.clone(), self.remote_cache_upload)
+                .with_max_cache_size(self.max_cache_size)
+                .with_materialization(self.materialize)
+        })
     }
 }
 
@@ -82,6 +115,7 @@ enum Goal {
     Build(BuildGoal),
     Cache(CacheGoal),
     DepGraph(DepGraphGoal),
+    Logs(LogsGoal),
     Rules(RulesGoal),
     Targets(TargetGoal),
     Toolchains(ToolchainGoal),
@@ -97,11 +131,19 @@ enum Goal {
 }
 
 impl Goal {
+    fn is_machine_readable(&self) -> bool {
+        match self {
+            Goal::Build(x) => x.is_machine_readable(),
+            _ => false,
+        }
+    }
+
     async fn run(self, config: ZapConfig) -> Result<(), anyhow::Error> {
         match self {
             Goal::Build(x) => x.run(config).await,
             Goal::Cache(x) => x.run(config).await,
             Goal::DepGraph(x) => x.run(config).await,
+            Goal::Logs(x) => x.run(config).await,
             Goal::Rules(x) => x.run(config).await,
             Goal::Targets(x) => x.run(config).await,
             Goal::Toolchains(x) => x.run(config).await,
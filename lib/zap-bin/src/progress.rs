@@ -0,0 +1,193 @@
+use std::io::{self, Write};
+use std::time::Instant;
+use zap_build_engine::*;
+use zap_core::*;
+
+/// The maximum number of running targets to list at once.
+const MAX_RUNNING_SHOWN: usize = 8;
+
+/// Shows the progress of a build on stdout.
+///
+/// When stdout is a terminal, a status block with how many targets are done
+/// and which ones are currently being built is redrawn in place. Otherwise,
+/// a line is printed for every target that starts or finishes building, so
+/// logs of CI builds are still readable.
+///
+pub struct TerminalProgress {
+    name: String,
+    interactive: bool,
+
+    total: usize,
+    finished: usize,
+    cached: usize,
+    failed: usize,
+    skipped: usize,
+
+    /// The targets being built, their mnemonic, and when they started.
+    running: Vec<(Label, String, Instant)>,
+
+    /// How many lines were drawn the last time, to clear them before redrawing.
+    drawn_lines: usize,
+
+    started_at: Instant,
+}
+
+impl TerminalProgress {
+    pub fn new(name: String) -> TerminalProgress {
+        TerminalProgress {
+            name,
+            interactive: atty::is(atty::Stream::Stdout),
+            total: 0,
+            finished: 0,
+            cached: 0,
+            failed: 0,
+            skipped: 0,
+            running: vec![],
+            drawn_lines: 0,
+            started_at: Instant::now(),
+        }
+    }
+
+    fn counts(&self) -> String {
+        let mut counts = format!(
+            "[{}/{}] {} cached, {} running",
+            self.finished,
+            self.total,
+            self.cached,
+            self.running.len()
+        );
+        if self.failed > 0 {
+            counts.push_str(&format!(", {} failed", self.failed));
+        }
+        if self.skipped > 0 {
+            counts.push_str(&format!(", {} skipped", self.skipped));
+        }
+        counts
+    }
+
+    fn clear(&mut self) {
+        if self.drawn_lines > 0 {
+            // move the cursor up to the first drawn line, and clear the
+            // screen from there on
+            print!("\x1b[{}A\x1b[J", self.drawn_lines);
+            self.drawn_lines = 0;
+        }
+    }
+
+    fn redraw(&mut self) {
+        if !self.interactive {
+            return;
+        }
+
+        self.clear();
+
+        let mut lines = vec![format!(
+            "🔨 Building {} {} {:.1}s",
+            self.name,
+            self.counts(),
+            self.started_at.elapsed().as_secs_f64()
+        )];
+        for (label, mnemonic, started_at) in self.running.iter().take(MAX_RUNNING_SHOWN) {
+            lines.push(format!(
+                "    {} {} {:.1}s",
+                mnemonic,
+                label.to_string(),
+                started_at.elapsed().as_secs_f64()
+            ));
+        }
+        if self.running.len() > MAX_RUNNING_SHOWN {
+            lines.push(format!(
+                "    ...and {} more",
+                self.running.len() - MAX_RUNNING_SHOWN
+            ));
+        }
+
+        for line in &lines {
+            println!("{}", line);
+        }
+        self.drawn_lines = lines.len();
+        io::stdout().flush().unwrap();
+    }
+
+    fn log(&self, line: String) {
+        if !self.interactive {
+            println!("[{}/{}] {}", self.finished, self.total, line);
+        }
+    }
+}
+
+impl BuildProgress for TerminalProgress {
+    fn started(&mut self, total: usize) {
+        self.total = total;
+        self.started_at = Instant::now();
+        if self.interactive {
+            self.redraw();
+        } else {
+            println!("🔨 Building {} ({} targets)", self.name, total);
+        }
+    }
+
+    fn cached(&mut self, label: &Label) {
+        self.finished += 1;
+        self.cached += 1;
+        self.log(format!("Cached {}", label.to_string()));
+        self.redraw();
+    }
+
+    fn building(&mut self, label: &Label, mnemonic: &str) {
+        self.running
+            .push((label.clone(), mnemonic.to_string(), Instant::now()));
+        self.log(format!("Building {} {}", mnemonic, label.to_string()));
+        self.redraw();
+    }
+
+    fn built(&mut self, label: &Label, succeeded: bool) {
+        let position = self.running.iter().position(|(l, _, _)| l == label);
+        let (mnemonic, elapsed) = match position {
+            Some(position) => {
+                let (_, mnemonic, started_at) = self.running.remove(position);
+                (mnemonic, started_at.elapsed())
+            }
+            None => ("".to_string(), Default::default()),
+        };
+
+        self.finished += 1;
+        if !succeeded {
+            self.failed += 1;
+        }
+        self.log(format!(
+            "{} {} {} ({:.1}s)",
+            if succeeded { "Built" } else { "Failed" },
+            mnemonic,
+            label.to_string(),
+            elapsed.as_secs_f64()
+        ));
+        self.redraw();
+    }
+
+    fn skipped(&mut self, label: &Label) {
+        self.finished += 1;
+        self.skipped += 1;
+        self.log(format!("Skipped {}", label.to_string()));
+        self.redraw();
+    }
+
+    fn tick(&mut self) {
+        self.redraw();
+    }
+
+    fn finished(&mut self) {
+        if self.interactive {
+            self.clear();
+            print!(
+                "🔨 Built {} {} in {:.1}s",
+                self.name,
+                self.counts(),
+                self.started_at.elapsed().as_secs_f64()
+            );
+            io::stdout().flush().unwrap();
+        } else {
+            print!("🔨 Done: {}", self.counts());
+        }
+    }
+}
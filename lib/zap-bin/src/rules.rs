@@ -61,8 +61,8 @@ impl RulesGoal {
     }
 
     fn dump_outputs(&self, target: &str, zap: &mut ZapWorker) -> Result<(), anyhow::Error> {
-        let label: Label = target.into();
-        let dep_graph = &mut zap.dep_graph.scoped(&label)?.seal(
+        let label = Label::parse(target)?;
+        let dep_graph = &mut zap.dep_graph.scoped(std::slice::from_ref(&label))?.seal(
             &zap.action_map,
             &zap.output_map,
             &mut zap.bs_ctx,
@@ -78,8 +78,8 @@ impl RulesGoal {
     }
 
     fn dump_actions(&self, target: &str, zap: &mut ZapWorker) -> Result<(), anyhow::Error> {
-        let label: Label = target.into();
-        let dep_graph = &mut zap.dep_graph.scoped(&label)?.seal(
+        let label = Label::parse(target)?;
+        let dep_graph = &mut zap.dep_graph.scoped(std::slice::from_ref(&label))?.seal(
             &zap.action_map,
             &zap.output_map,
             &mut zap.bs_ctx,
@@ -19,8 +19,17 @@ pub struct TargetGoal {
     setting = structopt::clap::AppSettings::ColoredHelp,
 )]
 enum Action {
-    #[structopt(help = r"List all the workspace targets")]
-    List,
+    #[structopt(
+        help = r"List all the workspace targets",
+        setting = structopt::clap::AppSettings::AllowLeadingHyphen
+    )]
+    List {
+        #[structopt(help = r"Only list the targets matching these patterns.
+
+Example: //my/library/... -//my/library/tests:all
+")]
+        patterns: Vec<String>,
+    },
 }
 
 impl TargetGoal {
@@ -30,13 +39,25 @@ impl TargetGoal {
         zap.build_dep_graph()?;
 
         match self.cmd {
-            Action::List => self.list_targets(&mut zap),
+            Action::List { ref patterns } => self.list_targets(patterns, &mut zap),
         }
     }
 
-    fn list_targets(&self, zap: &mut ZapWorker) -> Result<(), anyhow::Error> {
+    fn list_targets(&self, patterns: &[String], zap: &mut ZapWorker) -> Result<(), anyhow::Error> {
         let dep_graph = &mut zap.dep_graph;
-        let mut targets = dep_graph.target_names();
+        let mut targets = if patterns.is_empty() {
+            dep_graph.target_names()
+        } else {
+            let patterns = patterns
+                .iter()
+                .map(|pattern| TargetPattern::parse(pattern))
+                .collect::<Result<Vec<TargetPattern>, LabelError>>()?;
+            dep_graph
+                .find_matching(&patterns)?
+                .iter()
+                .map(|label| label.to_string())
+                .collect()
+        };
         targets.sort();
         for target in targets {
             println!("{}", target);
@@ -81,7 +81,7 @@ async fn build_dependency_graph_from_workspace_and_scope() {
     zap.load(&root).await.unwrap();
     zap.build_dep_graph().unwrap();
 
-    let dep_graph = zap.dep_graph.scoped(&Label::new("//b/c:lib")).unwrap();
+    let dep_graph = zap.dep_graph.scoped(&[Label::new("//b/c:lib")]).unwrap();
 
     let mut target_names_in_order = dep_graph.target_names();
     target_names_in_order.sort();
@@ -0,0 +1,71 @@
+//! Measures how long it takes to materialize a set of outputs with every
+//! strategy, the way outputs are promoted from the cache into a workspace.
+//!
+//! Usage: cargo run --release --example materialize -- [files] [file size in KB] [runs]
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use zap_build_engine::materialize;
+use zap_core::MaterializationStrategy;
+
+fn main() {
+    let args: Vec<usize> = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("Expected a number"))
+        .collect();
+    let files = args.first().cloned().unwrap_or(2000);
+    let size = args.get(1).cloned().unwrap_or(64) * 1024;
+    let runs = args.get(2).cloned().unwrap_or(10);
+
+    let root = std::env::temp_dir().join("zap-bench-materialize");
+    let _ = std::fs::remove_dir_all(&root);
+    let blobs = root.join("blobs");
+    std::fs::create_dir_all(&blobs).unwrap();
+    for i in 0..files {
+        let contents: Vec<u8> = (0..size).map(|b| (b + i) as u8).collect();
+        std::fs::write(blobs.join(i.to_string()), contents).unwrap();
+    }
+
+    println!(
+        "Materializing {} files of {}KB, mean of {} runs\n",
+        files,
+        size / 1024,
+        runs
+    );
+    println!("| Strategy | Mean | Per file |");
+    println!("|:---|---:|---:|");
+    for strategy in &[
+        MaterializationStrategy::Copy,
+        MaterializationStrategy::Hardlink,
+        MaterializationStrategy::Symlink,
+        MaterializationStrategy::Reflink,
+    ] {
+        let mut total = Duration::default();
+        for run in 0..runs {
+            let outputs = root.join(format!("{:?}-{}", strategy, run));
+            std::fs::create_dir_all(&outputs).unwrap();
+            total += time(*strategy, &blobs, &outputs, files);
+            std::fs::remove_dir_all(&outputs).unwrap();
+        }
+        let mean = total / runs as u32;
+        println!(
+            "| {:?} | {:.1} ms | {:.1} µs |",
+            strategy,
+            mean.as_secs_f64() * 1000.0,
+            mean.as_secs_f64() * 1_000_000.0 / files as f64
+        );
+    }
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+fn time(strategy: MaterializationStrategy, blobs: &Path, outputs: &Path, files: usize) -> Duration {
+    let paths: Vec<(PathBuf, PathBuf)> = (0..files)
+        .map(|i| (blobs.join(i.to_string()), outputs.join(i.to_string())))
+        .collect();
+    let t0 = Instant::now();
+    for (src, dst) in &paths {
+        materialize(strategy, src, dst).unwrap();
+    }
+    t0.elapsed()
+}
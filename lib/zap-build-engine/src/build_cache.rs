@@ -1,17 +1,149 @@
-use super::Sandbox;
-use anyhow::Context;
+use super::{
+    materialize, pack_bundle, unpack_bundle, BlobStore, CacheBackend, CacheManifest,
+    HttpCacheBackend, Sandbox,
+};
+use anyhow::{anyhow, Context};
+use dashmap::DashMap;
 use log::*;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use zap_core::{ComputedTarget, Label, ZapConfig};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use zap_core::{ComputedTarget, Label, MaterializationStrategy, ZapConfig};
 
 /// The BuildCache implements an in-memory and persisted cache for build nodes
 /// based on their hashes.
 ///
+/// Outputs are stored in a content-addressed BlobStore under `blobs`, and
+/// every cached node has a manifest under `manifests/<hash>` mapping its
+/// outputs to their blobs. This way identical outputs are stored only once.
+///
+/// Every manifest records the workspaces that built or used its node, so
+/// that garbage collecting the cache from one workspace doesn't remove
+/// entries that other workspaces still need.
+///
+/// Optionally, it can be backed by a remote CacheBackend. Local cache misses
+/// will be looked up in the remote, and if `upload_to_remote` is set, every
+/// newly cached node will be uploaded to it as well.
+///
 #[derive(Debug, Clone)]
 pub struct BuildCache {
     root: PathBuf,
-    memcache: HashMap<String, Label>,
+    blobs: BlobStore,
+    materialization: MaterializationStrategy,
+    memcache: DashMap<String, Label>,
+    verified: DashMap<String, ()>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    remote: Option<Arc<dyn CacheBackend>>,
+    upload_to_remote: bool,
+
+    /// The root of the workspace using this cache, if any.
+    workspace: Option<PathBuf>,
+}
+
+/// A node stored in the BuildCache.
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub hash: String,
+    pub manifest: CacheManifest,
+
+    /// The size of the manifest on disk, in bytes.
+    pub size: u64,
+
+    /// The last time this entry was written, or used by a build.
+    pub last_used: SystemTime,
+}
+
+/// The outcome of a garbage collection of the BuildCache.
+#[derive(Debug, Clone, Default)]
+pub struct CacheGcReport {
+    pub kept_entries: usize,
+
+    /// The entries kept only because other workspaces use them.
+    pub shared_entries: usize,
+
+    /// The directories left behind by the cache layout from before manifests
+    /// and blobs, which no build can use anymore.
+    pub removed_legacy_entries: usize,
+
+    pub removed_entries: Vec<CacheEntry>,
+    pub removed_blobs: usize,
+
+    /// The bytes on disk that were (or would be, on a dry run) freed.
+    pub reclaimable_bytes: u64,
+}
+
+/// A summary of the contents and usage of the BuildCache.
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub blobs: usize,
+
+    /// The bytes on disk used by all manifests and blobs.
+    pub total_bytes: u64,
+
+    /// The number of cache hits and misses of local targets across all builds.
+    pub hits: u64,
+    pub misses: u64,
+
+    /// Every entry along with the size of its outputs, largest first.
+    pub largest_entries: Vec<(CacheEntry, u64)>,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> Option<f64> {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            None
+        } else {
+            Some(self.hits as f64 / total as f64)
+        }
+    }
+}
+
+/// The outcome of evicting entries from the BuildCache to fit a size limit.
+#[derive(Debug, Clone, Default)]
+pub struct CacheEvictionReport {
+    pub evicted_entries: usize,
+    pub freed_bytes: u64,
+
+    /// The bytes on disk used by the cache after the eviction.
+    pub total_bytes: u64,
+}
+
+/// A problem found when verifying an output of a cache entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputCorruption {
+    /// The blob of the output is not in the cache.
+    Missing { path: PathBuf, digest: String },
+
+    /// The contents of the blob of the output don't match its digest.
+    Tampered { path: PathBuf, digest: String },
+
+    /// The output was expected but is not in the manifest.
+    Undeclared { path: PathBuf },
+}
+
+impl std::fmt::Display for OutputCorruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutputCorruption::Missing { path, digest } => {
+                write!(f, "output {:?} is missing (expected blob {})", path, digest)
+            }
+            OutputCorruption::Tampered { path, digest } => {
+                write!(
+                    f,
+                    "output {:?} was modified (expected blob {})",
+                    path, digest
+                )
+            }
+            OutputCorruption::Undeclared { path } => {
+                write!(f, "output {:?} is not in the manifest", path)
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -19,21 +151,54 @@ pub enum CacheHitType {
     Miss,
     Global,
     Local,
+    Remote,
 }
 
 impl BuildCache {
     pub fn new(config: &ZapConfig) -> BuildCache {
-        BuildCache {
+        let cache = BuildCache {
             root: config.cache_root.clone(),
-            memcache: HashMap::<String, Label>::new(),
+            blobs: BlobStore::new(config.cache_root.join("blobs")),
+            materialization: config.materialization,
+            memcache: DashMap::<String, Label>::new(),
+            verified: DashMap::new(),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            remote: None,
+            upload_to_remote: false,
+            workspace: None,
+        };
+
+        match &config.remote_cache {
+            Some(url) => cache.with_remote(
+                Arc::new(HttpCacheBackend::new(url)),
+                config.remote_cache_upload,
+            ),
+            None => cache,
         }
     }
 
-    pub fn save(&mut self, sandbox: &Sandbox) -> Result<(), anyhow::Error> {
+    pub fn with_remote(self, remote: Arc<dyn CacheBackend>, upload_to_remote: bool) -> BuildCache {
+        BuildCache {
+            remote: Some(remote),
+            upload_to_remote,
+            ..self
+        }
+    }
+
+    /// Record every node saved or used through this cache as used by the
+    /// workspace at `root`.
+    pub fn with_workspace(self, root: &Path) -> BuildCache {
+        BuildCache {
+            workspace: Some(std::fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf())),
+            ..self
+        }
+    }
+
+    pub fn save(&self, sandbox: &Sandbox) -> Result<(), anyhow::Error> {
         let node = sandbox.node();
         let hash = node.hash();
         self.memcache.insert(hash.clone(), node.label().clone());
-        let cache_path = self.root.join(&hash);
 
         debug!(
             "Caching node {:?} hashed {:?}: {:?} outputs",
@@ -42,39 +207,325 @@ impl BuildCache {
             sandbox.outputs().len()
         );
 
-        sandbox
-            .outputs()
-            .iter()
-            .cloned()
-            .map(|artifact| {
-                debug!("Caching build artifact: {:?}", &artifact);
-                let cached_file = cache_path.join(&artifact);
-
-                // ensure all dirs are there for this file
-                let cached_dir = &cached_file.parent().unwrap();
-                debug!("Creating artifact cache path: {:?}", &cached_dir);
-                std::fs::create_dir_all(&cached_dir)
-                    .context(format!(
-                        "Could not prepare directory for artifact {:?} into cache path: {:?}",
-                        &artifact, &cached_dir
-                    ))
-                    .map(|_| ())?;
-
-                let sandboxed_artifact = &sandbox.root().join(artifact);
-                debug!(
-                    "Moving artifact from sandbox path {:?} to cache path {:?}",
-                    &sandboxed_artifact, &cached_file
+        let mut manifest = self.owned(CacheManifest::new().with_label(node.label().to_string()));
+        for artifact in sandbox.outputs() {
+            let sandboxed_artifact = sandbox.root().join(&artifact);
+            debug!(
+                "Moving artifact from sandbox path {:?} into the blob store",
+                &sandboxed_artifact
+            );
+            let digest = self.blobs.insert(&sandboxed_artifact).context(format!(
+                "Could not cache artifact {:?} of target {}",
+                &artifact,
+                node.label().to_string()
+            ))?;
+            debug!("Cached build artifact {:?} as blob {}", &artifact, &digest);
+            manifest.add(artifact.clone(), digest);
+        }
+
+        manifest.write(&self.manifest_path(&hash))?;
+
+        if self.upload_to_remote {
+            self.upload(&hash, &manifest);
+        }
+
+        Ok(())
+    }
+
+    /// The path to the manifest of a cached node.
+    fn manifest_path(&self, hash: &str) -> PathBuf {
+        self.root.join("manifests").join(hash)
+    }
+
+    /// Read the manifest of a cached node.
+    pub fn manifest(&self, hash: &str) -> Result<CacheManifest, anyhow::Error> {
+        CacheManifest::read(&self.manifest_path(hash))
+    }
+
+    /// Find where in the cache a given output of a cached node is stored.
+    pub fn output_path(&self, hash: &str, out: &Path) -> Result<PathBuf, anyhow::Error> {
+        let manifest = self.manifest(hash)?;
+        let digest = manifest.digest_of(out).context(format!(
+            "Output {:?} is not part of cache entry {}, has the cache been modified manually?",
+            out, hash
+        ))?;
+        Ok(self.blobs.path(digest))
+    }
+
+    /// Remove a node from the cache.
+    ///
+    /// NOTE: blobs may be shared with other nodes, so they are left in place.
+    pub fn remove(&self, hash: &str) -> Result<(), anyhow::Error> {
+        let manifest_path = self.manifest_path(hash);
+        std::fs::remove_file(&manifest_path).context(format!(
+            "Could not remove cache manifest {:?}",
+            &manifest_path
+        ))
+    }
+
+    /// List every node stored in the local cache.
+    ///
+    /// NOTE: manifests that can't be read are skipped with a warning, since
+    /// they can't be used by a build anyway.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, anyhow::Error> {
+        let manifests_root = self.root.join("manifests");
+        let mut entries = vec![];
+        if !manifests_root.is_dir() {
+            return Ok(entries);
+        }
+
+        for file in std::fs::read_dir(&manifests_root).context(format!(
+            "Could not list cache manifests at {:?}",
+            &manifests_root
+        ))? {
+            let path = file?.path();
+            if path.extension().is_some() {
+                continue;
+            }
+            let hash = path.file_name().unwrap().to_string_lossy().to_string();
+            let metadata = std::fs::metadata(&path)?;
+            match CacheManifest::read(&path) {
+                Ok(manifest) => entries.push(CacheEntry {
+                    hash,
+                    manifest,
+                    size: metadata.len(),
+                    last_used: metadata.modified()?,
+                }),
+                Err(err) => warn!("Skipping cache entry {}: {:?}", hash, err),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Remove every node in the cache that is not reachable from the current
+    /// build graph, as described in `spec/Crane_Cache.tla`, and every blob
+    /// that is no longer referenced by any remaining node.
+    ///
+    /// `live` is the set of hashes of the nodes in the current build graph.
+    /// On top of those, the `keep` most recent entries of every label are kept
+    /// as well, so that switching back and forth between branches doesn't
+    /// need a rebuild.
+    ///
+    /// If this cache belongs to a workspace, entries used by other workspaces
+    /// that still exist, or that don't record who uses them, are kept too.
+    ///
+    /// Directories left behind by the old cache layout, where every node had
+    /// a `<hash>` directory with its outputs, are always removed.
+    ///
+    /// On a dry run, nothing is removed, but the report is still computed.
+    ///
+    /// NOTE: this should not run at the same time as a build, since blobs are
+    /// stored before the manifests that refer to them.
+    pub fn gc(
+        &self,
+        live: &HashSet<String>,
+        keep: usize,
+        dry_run: bool,
+    ) -> Result<CacheGcReport, anyhow::Error> {
+        let mut report = CacheGcReport::default();
+
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+
+        let mut kept_per_label: HashMap<String, usize> = HashMap::new();
+        let mut referenced: HashSet<String> = HashSet::new();
+        for entry in entries {
+            let is_recent = match entry.manifest.label() {
+                Some(label) => {
+                    let kept = kept_per_label.entry(label.to_string()).or_insert(0);
+                    *kept += 1;
+                    *kept <= keep
+                }
+                None => false,
+            };
+
+            let is_shared = self.is_used_elsewhere(&entry.manifest);
+
+            if live.contains(&entry.hash) || is_recent || is_shared {
+                for (_, digest) in entry.manifest.outputs() {
+                    referenced.insert(digest.clone());
+                }
+                report.kept_entries += 1;
+                if is_shared && !live.contains(&entry.hash) && !is_recent {
+                    report.shared_entries += 1;
+                }
+                continue;
+            }
+
+            debug!("Collecting unreachable cache entry {}", &entry.hash);
+            report.reclaimable_bytes += entry.size;
+            if !dry_run {
+                self.remove(&entry.hash)?;
+            }
+            report.removed_entries.push(entry);
+        }
+
+        for (digest, size) in self.blobs.list()? {
+            if referenced.contains(&digest) {
+                continue;
+            }
+            debug!("Collecting unreferenced blob {}", &digest);
+            report.removed_blobs += 1;
+            report.reclaimable_bytes += size;
+            if !dry_run {
+                self.blobs.remove(&digest)?;
+            }
+        }
+
+        for dir in self.legacy_entries()? {
+            debug!("Collecting legacy cache entry {:?}", &dir);
+            report.removed_legacy_entries += 1;
+            report.reclaimable_bytes += fs_extra::dir::get_size(&dir).unwrap_or(0);
+            if !dry_run {
+                std::fs::remove_dir_all(&dir)
+                    .context(format!("Could not remove legacy cache entry {:?}", &dir))?;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Whether an entry may still be needed by a workspace other than the one
+    /// this cache belongs to.
+    fn is_used_elsewhere(&self, manifest: &CacheManifest) -> bool {
+        match &self.workspace {
+            None => false,
+            Some(root) => {
+                manifest.workspaces().is_empty()
+                    || manifest
+                        .workspaces()
+                        .iter()
+                        .any(|other| other != root && other.is_dir())
+            }
+        }
+    }
+
+    /// The `<hash>` directories of the old cache layout.
+    ///
+    /// NOTE: global targets are still cached in `<name>-<hash>` directories,
+    /// so only directories named exactly like a hash are legacy entries.
+    fn legacy_entries(&self) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let mut dirs = vec![];
+        if !self.root.is_dir() {
+            return Ok(dirs);
+        }
+        for entry in std::fs::read_dir(&self.root)
+            .context(format!("Could not list the cache at {:?}", &self.root))?
+        {
+            let path = entry?.path();
+            let is_hash = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.len() == 40 && name.chars().all(|c| c.is_ascii_hexdigit()))
+                .unwrap_or(false);
+            if is_hash && path.is_dir() {
+                dirs.push(path);
+            }
+        }
+        Ok(dirs)
+    }
+
+    /// Upload a cached node to the remote cache.
+    ///
+    /// Bundles have the manifest of the node at `manifest`, and every blob it
+    /// refers to at `blobs/<digest>`. The workspaces using the node are left
+    /// out of the manifest, since they are paths on this machine.
+    ///
+    /// NOTE: a failed upload should never fail the build, since the outputs
+    /// are already safely in the local cache.
+    fn upload(&self, hash: &str, manifest: &CacheManifest) {
+        if let Some(remote) = &self.remote {
+            let manifest_path = self.root.join(format!(".upload-{}", hash));
+            let result = manifest
+                .clone()
+                .without_workspaces()
+                .write(&manifest_path)
+                .and_then(|()| {
+                    let mut files = vec![(PathBuf::from("manifest"), manifest_path.clone())];
+                    for (_, digest) in manifest.outputs() {
+                        files.push((PathBuf::from("blobs").join(digest), self.blobs.path(digest)));
+                    }
+                    pack_bundle(&files)
+                })
+                .and_then(|bundle| remote.put(hash, &bundle));
+            let _ = std::fs::remove_file(&manifest_path);
+
+            if let Err(err) = result {
+                warn!(
+                    "Could not upload {} to remote cache {}: {:?}",
+                    hash,
+                    remote.name(),
+                    err
                 );
-                std::fs::rename(&sandboxed_artifact, &cached_file)
-                    .context(format!(
-                        "Could not move artifact {:?} into cache path: {:?}",
-                        sandboxed_artifact, cached_file
-                    ))
-                    .map(|_| ())?;
-
-                Ok(())
-            })
-            .collect::<Result<(), anyhow::Error>>()
+            }
+        }
+    }
+
+    /// Try to fetch a node from the remote cache into the local cache.
+    ///
+    /// Bundles are unpacked into a temporary directory first, and every blob
+    /// is rehashed on its way into the blob store. The manifest is written
+    /// last, so a broken download never looks like a local cache hit.
+    ///
+    /// NOTE: an unreachable or misbehaving remote cache is treated as a cache
+    /// miss, so that it never fails the build.
+    fn fetch(&self, hash: &str) -> bool {
+        let remote = match &self.remote {
+            Some(remote) => remote,
+            None => return false,
+        };
+
+        let tmp_path = self.root.join(format!(".remote-{}", hash));
+        let result = remote.get(hash).and_then(|bundle| match bundle {
+            Some(bundle) => {
+                let _ = std::fs::remove_dir_all(&tmp_path);
+                unpack_bundle(&bundle, &tmp_path)?;
+
+                let manifest = CacheManifest::read(&tmp_path.join("manifest"))?;
+                for (out, digest) in manifest.outputs() {
+                    // NOTE: digests become paths, so one from a misbehaving
+                    // remote must never point outside of the bundle.
+                    if !BlobStore::is_digest(digest) {
+                        return Err(anyhow!(
+                            "Output {:?} has an invalid digest {:?}",
+                            out,
+                            digest
+                        ));
+                    }
+                    let actual = self.blobs.insert(&tmp_path.join("blobs").join(digest))?;
+                    if actual != *digest {
+                        return Err(anyhow!(
+                            "Output {:?} was expected to have digest {} but had {}",
+                            out,
+                            digest,
+                            actual
+                        ));
+                    }
+                }
+                // NOTE: only this workspace uses the entry so far, whatever
+                // the remote manifest says.
+                self.owned(manifest.without_workspaces())
+                    .write(&self.manifest_path(hash))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        });
+
+        let _ = std::fs::remove_dir_all(&tmp_path);
+
+        match result {
+            Ok(found) => found,
+            Err(err) => {
+                warn!(
+                    "Could not fetch {} from remote cache {}: {:?}",
+                    hash,
+                    remote.name(),
+                    err
+                );
+                false
+            }
+        }
     }
 
     pub fn promote_outputs(
@@ -84,51 +535,347 @@ impl BuildCache {
     ) -> Result<(), anyhow::Error> {
         trace!("Promoting outputs for {}", node.target.label().to_string());
         let hash = node.hash();
-        let hash_path = self.root.join(&hash);
+        let manifest = self.manifest(&hash)?;
 
         let mut paths: HashMap<PathBuf, ()> = HashMap::new();
         let mut outs: HashMap<PathBuf, PathBuf> = HashMap::new();
         for out in node.outs() {
+            let digest = manifest.digest_of(&out).context(format!(
+                "Output {:?} of target {} is not in the cache, has the cache been modified manually?",
+                &out,
+                node.label().to_string()
+            ))?;
             paths.insert(dst.join(&out).parent().unwrap().to_path_buf(), ());
-            outs.insert(hash_path.join(&out), dst.join(&out).to_path_buf());
+            outs.insert(self.blobs.path(digest), dst.join(&out).to_path_buf());
         }
 
         for (path, _) in paths {
             std::fs::create_dir_all(&path)?;
         }
         for (src, dst) in outs {
-            std::fs::copy(&src, &dst)?;
+            materialize(self.materialization, &src, &dst).context(format!(
+                "Could not promote cached output {:?} to {:?}",
+                &src, &dst
+            ))?;
+        }
+
+        self.touch(&hash);
+        Ok(())
+    }
+
+    /// Check that every output of a cached node is in its manifest, and that
+    /// its blob still has the right contents.
+    ///
+    /// If it doesn't, the entry is discarded with a warning so the node gets
+    /// rebuilt.
+    fn validate(&self, node: &ComputedTarget) -> bool {
+        let hash = node.hash();
+        let manifest = match self.manifest(&hash) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                warn!(
+                    "Cache entry for {} is corrupted, rebuilding it: {:?}",
+                    node.label().to_string(),
+                    err
+                );
+                let _ = self.remove(&hash);
+                return false;
+            }
+        };
+
+        let mut corruptions = self.verify_manifest(&manifest);
+        for out in node.outs() {
+            if manifest.digest_of(&out).is_none() {
+                corruptions.push(OutputCorruption::Undeclared { path: out });
+            }
+        }
+
+        if corruptions.is_empty() {
+            return true;
+        }
+
+        warn!(
+            "Cache entry for {} is corrupted, rebuilding it: {}",
+            node.label().to_string(),
+            corruptions
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        if let Err(err) = self.discard(&hash, &corruptions) {
+            warn!("{:?}", err);
+        }
+        false
+    }
+
+    /// Rehash every blob referenced by a manifest, returning the outputs that
+    /// are missing or were modified.
+    ///
+    /// Blobs are shared across entries, so every blob is only rehashed once.
+    pub fn verify_manifest(&self, manifest: &CacheManifest) -> Vec<OutputCorruption> {
+        let mut corruptions = vec![];
+        for (path, digest) in manifest.outputs() {
+            if self.verified.contains_key(digest) {
+                continue;
+            }
+            if !self.blobs.contains(digest) {
+                corruptions.push(OutputCorruption::Missing {
+                    path: path.clone(),
+                    digest: digest.clone(),
+                });
+            } else if !self.blobs.verify(digest) {
+                corruptions.push(OutputCorruption::Tampered {
+                    path: path.clone(),
+                    digest: digest.clone(),
+                });
+            } else {
+                self.verified.insert(digest.clone(), ());
+            }
+        }
+        corruptions
+    }
+
+    /// Verify every entry in the cache, returning the corrupted ones.
+    pub fn verify(&self) -> Result<Vec<(CacheEntry, Vec<OutputCorruption>)>, anyhow::Error> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| {
+                let corruptions = self.verify_manifest(&entry.manifest);
+                if corruptions.is_empty() {
+                    None
+                } else {
+                    Some((entry, corruptions))
+                }
+            })
+            .collect())
+    }
+
+    /// Remove a corrupted entry, along with its tampered blobs, since their
+    /// contents no longer match their digest.
+    pub fn discard(
+        &self,
+        hash: &str,
+        corruptions: &[OutputCorruption],
+    ) -> Result<(), anyhow::Error> {
+        for corruption in corruptions {
+            if let OutputCorruption::Tampered { digest, .. } = corruption {
+                if self.blobs.contains(digest) {
+                    self.blobs.remove(digest)?;
+                }
+            }
+        }
+        self.remove(hash)
+    }
+
+    /// Mark a node as used by the current build.
+    ///
+    /// The last access of an entry is kept as the modification time of its
+    /// manifest, and it is what the LRU eviction in `evict` is based on.
+    ///
+    /// NOTE: failing to update it should never fail the build, at worst the
+    /// entry will be evicted earlier than it should.
+    fn touch(&self, hash: &str) {
+        let manifest_path = self.manifest_path(hash);
+        if let Err(err) = self.claim(&manifest_path) {
+            warn!(
+                "Could not record {:?} as used by this workspace: {:?}",
+                &manifest_path, err
+            );
+        }
+        let result = std::fs::File::options()
+            .write(true)
+            .open(&manifest_path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        if let Err(err) = result {
+            warn!(
+                "Could not update last access of {:?}: {:?}",
+                &manifest_path, err
+            );
+        }
+    }
+
+    /// Add the workspace of this cache to a manifest.
+    fn owned(&self, manifest: CacheManifest) -> CacheManifest {
+        match &self.workspace {
+            Some(root) => manifest.with_workspace(root),
+            None => manifest,
+        }
+    }
+
+    /// Record that the workspace of this cache uses an existing entry.
+    fn claim(&self, manifest_path: &Path) -> Result<(), anyhow::Error> {
+        if let Some(root) = &self.workspace {
+            let manifest = CacheManifest::read(manifest_path)?;
+            if !manifest.workspaces().contains(root) {
+                manifest.with_workspace(root).write(manifest_path)?;
+            }
         }
         Ok(())
     }
 
-    pub fn absolute_path_by_hash(&self, hash: &str) -> PathBuf {
-        let path = self.root.join(hash);
-        std::fs::canonicalize(&path).unwrap_or_else(|_| {
-            panic!(
-                "Could not find {:?} in disk, has the cache been modified manually?",
-                &path
-            )
+    fn record_access(&self, node: &ComputedTarget, hit: bool) {
+        if !node.target.is_local() {
+            return;
+        }
+        if hit {
+            self.memcache.insert(node.hash(), node.label().clone());
+            self.hits.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.misses.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Add the hits and misses of this build to the ones persisted in the
+    /// cache.
+    pub fn save_stats(&self) -> Result<(), anyhow::Error> {
+        let (hits, misses) = self.read_stats();
+        let stats_path = self.root.join("stats");
+        std::fs::write(
+            &stats_path,
+            format!(
+                "hits {}\nmisses {}\n",
+                hits + self.hits.swap(0, Ordering::SeqCst),
+                misses + self.misses.swap(0, Ordering::SeqCst)
+            ),
+        )
+        .context(format!("Could not save cache stats to {:?}", &stats_path))
+    }
+
+    fn read_stats(&self) -> (u64, u64) {
+        let contents = std::fs::read_to_string(self.root.join("stats")).unwrap_or_default();
+        let mut hits = 0;
+        let mut misses = 0;
+        for line in contents.lines() {
+            let mut parts = line.split(' ');
+            match (parts.next(), parts.next().and_then(|n| n.parse().ok())) {
+                (Some("hits"), Some(n)) => hits = n,
+                (Some("misses"), Some(n)) => misses = n,
+                _ => (),
+            }
+        }
+        (hits, misses)
+    }
+
+    /// Summarize the contents and usage of the cache.
+    pub fn stats(&self) -> Result<CacheStats, anyhow::Error> {
+        let entries = self.entries()?;
+        let blobs: HashMap<String, u64> = self.blobs.list()?.into_iter().collect();
+        let (hits, misses) = self.read_stats();
+
+        let mut largest_entries: Vec<(CacheEntry, u64)> = entries
+            .into_iter()
+            .map(|entry| {
+                let size = entry
+                    .manifest
+                    .outputs()
+                    .iter()
+                    .map(|(_, digest)| blobs.get(digest).cloned().unwrap_or(0))
+                    .sum();
+                (entry, size)
+            })
+            .collect();
+        largest_entries.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+        Ok(CacheStats {
+            entries: largest_entries.len(),
+            blobs: blobs.len(),
+            total_bytes: largest_entries
+                .iter()
+                .map(|(entry, _)| entry.size)
+                .chain(blobs.values().cloned())
+                .sum(),
+            hits,
+            misses,
+            largest_entries,
         })
     }
 
+    /// Evict the least recently used entries until the cache fits in
+    /// `max_bytes`, removing the blobs that are no longer referenced.
+    ///
+    /// Entries used by the current build are never evicted, so the cache may
+    /// still be over the limit if a single build doesn't fit in it.
+    pub fn evict(&self, max_bytes: u64) -> Result<CacheEvictionReport, anyhow::Error> {
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|entry| entry.last_used);
+
+        let blobs: HashMap<String, u64> = self.blobs.list()?.into_iter().collect();
+        let mut references: HashMap<String, usize> = HashMap::new();
+        for entry in &entries {
+            for (_, digest) in entry.manifest.outputs() {
+                *references.entry(digest.clone()).or_insert(0) += 1;
+            }
+        }
+
+        let mut report = CacheEvictionReport {
+            total_bytes: entries
+                .iter()
+                .map(|entry| entry.size)
+                .chain(blobs.values().cloned())
+                .sum(),
+            ..CacheEvictionReport::default()
+        };
+
+        for entry in entries {
+            if report.total_bytes <= max_bytes {
+                break;
+            }
+            if self.memcache.contains_key(&entry.hash) {
+                continue;
+            }
+
+            debug!("Evicting cache entry {}", &entry.hash);
+            self.remove(&entry.hash)?;
+            let mut freed = entry.size;
+            for (_, digest) in entry.manifest.outputs() {
+                let count = references.get_mut(digest).unwrap();
+                *count -= 1;
+                if *count == 0 {
+                    if let Some(size) = blobs.get(digest) {
+                        self.blobs.remove(digest)?;
+                        freed += size;
+                    }
+                }
+            }
+
+            report.evicted_entries += 1;
+            report.freed_bytes += freed;
+            report.total_bytes -= freed;
+        }
+
+        if report.total_bytes > max_bytes {
+            warn!(
+                "Cache is still using {} bytes, over the limit of {} bytes, after evicting {} entries",
+                report.total_bytes, max_bytes, report.evicted_entries
+            );
+        }
+
+        Ok(report)
+    }
+
     /// Determine if a given node has been cached already or not.
     ///
-    /// This is based on hash of the node (see `BuildRule::hash`).
+    /// This is based on hash of the node (see `BuildRule::hash`). Nodes that
+    /// are not in the local cache will be looked up in the remote cache, if
+    /// there is one.
     ///
-    /// FIXME: check if the expected hashes of the inputs match the actual
-    /// hash of the files to determine if the cache is corrupted.
-    pub fn is_cached(&mut self, node: &ComputedTarget) -> Result<CacheHitType, anyhow::Error> {
+    /// Local entries are verified against their manifest before being used.
+    /// Corrupted entries are discarded and treated as a cache miss.
+    pub fn is_cached(&self, node: &ComputedTarget) -> Result<CacheHitType, anyhow::Error> {
         let hash = node.hash();
 
-        let hash_path = self.root.join(&hash);
-        debug!("Checking if {:?} is in the cache...", hash_path);
-        if std::fs::metadata(&hash_path).is_ok() {
+        let manifest_path = self.manifest_path(&hash);
+        debug!("Checking if {:?} is in the cache...", manifest_path);
+        if std::fs::metadata(&manifest_path).is_ok() && self.validate(node) {
             debug!(
                 "Cache hit for {} at {:?}",
                 node.label().to_string(),
-                hash_path
+                manifest_path
             );
+            self.touch(&hash);
+            self.record_access(node, true);
             return Ok(CacheHitType::Local);
         }
 
@@ -143,7 +890,424 @@ impl BuildCache {
             return Ok(CacheHitType::Global);
         }
 
+        if self.fetch(&hash) {
+            debug!("Remote cache hit for {}", node.label().to_string());
+            self.record_access(node, true);
+            return Ok(CacheHitType::Remote);
+        }
+
         debug!("No cache hit for {}", node.label().to_string());
+        self.record_access(node, false);
         Ok(CacheHitType::Miss)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fixture_dir;
+    use crate::make_writable;
+    use std::time::Duration;
+
+    fn cache_at(name: &str) -> BuildCache {
+        let root = fixture_dir("cache", name);
+        BuildCache {
+            blobs: BlobStore::new(root.join("blobs")),
+            materialization: MaterializationStrategy::Copy,
+            root,
+            memcache: DashMap::new(),
+            verified: DashMap::new(),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            remote: None,
+            upload_to_remote: false,
+            workspace: None,
+        }
+    }
+
+    /// Store an entry in the cache as if it had been built `age` seconds ago.
+    fn store_entry(cache: &BuildCache, hash: &str, label: &str, outs: &[(&str, &str)], age: u64) {
+        let mut manifest = CacheManifest::new().with_label(label.to_string());
+        for (out, contents) in outs {
+            let file = cache.root.join("staging");
+            std::fs::write(&file, contents).unwrap();
+            let digest = cache.blobs.insert(&file).unwrap();
+            manifest.add(PathBuf::from(out), digest);
+        }
+
+        let manifest_path = cache.manifest_path(hash);
+        manifest.write(&manifest_path).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&manifest_path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+    }
+
+    fn live(hashes: &[&str]) -> HashSet<String> {
+        hashes.iter().map(|h| h.to_string()).collect()
+    }
+
+    fn removed(report: &CacheGcReport) -> Vec<String> {
+        let mut hashes: Vec<String> = report
+            .removed_entries
+            .iter()
+            .map(|e| e.hash.clone())
+            .collect();
+        hashes.sort();
+        hashes
+    }
+
+    #[test]
+    fn collects_unreachable_entries_and_blobs() {
+        let cache = cache_at("gc");
+        store_entry(&cache, "new-a", "//a:a", &[("a.beam", "new a")], 0);
+        store_entry(&cache, "old-a", "//a:a", &[("a.beam", "old a")], 10);
+        store_entry(&cache, "old-b", "//b:b", &[("b.beam", "new a")], 10);
+
+        let report = cache.gc(&live(&["new-a"]), 0, false).unwrap();
+
+        assert_eq!(vec!["old-a", "old-b"], removed(&report));
+        assert_eq!(1, report.kept_entries);
+        // the blob of `old-b` is shared with `new-a`, so only one is removed
+        assert_eq!(1, report.removed_blobs);
+        let hashes: Vec<String> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.hash)
+            .collect();
+        assert_eq!(vec!["new-a"], hashes);
+        assert_eq!(1, cache.blobs.list().unwrap().len());
+        assert!(cache
+            .output_path("new-a", Path::new("a.beam"))
+            .unwrap()
+            .is_file());
+    }
+
+    #[test]
+    fn dry_runs_only_report_reclaimable_bytes() {
+        let cache = cache_at("gc-dry-run");
+        store_entry(&cache, "new-a", "//a:a", &[("a.beam", "new a")], 0);
+        store_entry(&cache, "old-a", "//a:a", &[("a.beam", "old a!")], 10);
+
+        let report = cache.gc(&live(&["new-a"]), 0, true).unwrap();
+
+        assert_eq!(vec!["old-a"], removed(&report));
+        assert_eq!(1, report.removed_blobs);
+        let manifest_size = std::fs::metadata(cache.manifest_path("old-a"))
+            .unwrap()
+            .len();
+        assert_eq!(manifest_size + 6, report.reclaimable_bytes);
+        assert_eq!(2, cache.entries().unwrap().len());
+        assert_eq!(2, cache.blobs.list().unwrap().len());
+    }
+
+    #[test]
+    fn keeps_the_most_recent_entries_of_every_label() {
+        let cache = cache_at("gc-keep");
+        store_entry(&cache, "a-1", "//a:a", &[("a.beam", "a 1")], 0);
+        store_entry(&cache, "a-2", "//a:a", &[("a.beam", "a 2")], 10);
+        store_entry(&cache, "a-3", "//a:a", &[("a.beam", "a 3")], 20);
+        store_entry(&cache, "b-1", "//b:b", &[("b.beam", "b 1")], 30);
+
+        let report = cache.gc(&live(&[]), 2, false).unwrap();
+
+        assert_eq!(vec!["a-3"], removed(&report));
+        assert_eq!(3, report.kept_entries);
+    }
+
+    #[test]
+    fn keeps_entries_used_by_other_workspaces() {
+        let cache = cache_at("gc-workspaces");
+        let ours = cache.root.join("workspaces/ours");
+        let theirs = cache.root.join("workspaces/theirs");
+        let gone = cache.root.join("workspaces/gone");
+        std::fs::create_dir_all(&ours).unwrap();
+        std::fs::create_dir_all(&theirs).unwrap();
+        let cache = cache.with_workspace(&ours);
+        let ours = std::fs::canonicalize(&ours).unwrap();
+        let theirs = std::fs::canonicalize(&theirs).unwrap();
+
+        let used_by = |hash: &str, workspaces: &[&Path]| {
+            store_entry(&cache, hash, &format!("//{}:{}", hash, hash), &[], 0);
+            let manifest = workspaces
+                .iter()
+                .fold(cache.manifest(hash).unwrap(), |m, root| {
+                    m.with_workspace(root)
+                });
+            manifest.write(&cache.manifest_path(hash)).unwrap();
+        };
+        used_by("ours", &[&ours]);
+        used_by("both", &[&ours, &theirs]);
+        used_by("theirs", &[&theirs]);
+        used_by("ours-and-gone", &[&ours, &gone]);
+        used_by("unknown", &[]);
+
+        let report = cache.gc(&live(&[]), 0, true).unwrap();
+        assert_eq!(vec!["ours", "ours-and-gone"], removed(&report));
+        assert_eq!(3, report.shared_entries);
+
+        let everywhere = BuildCache {
+            workspace: None,
+            ..cache.clone()
+        };
+        let report = everywhere.gc(&live(&[]), 0, true).unwrap();
+        assert_eq!(5, report.removed_entries.len());
+    }
+
+    #[test]
+    fn records_the_workspaces_using_an_entry() {
+        let cache = cache_at("claim");
+        store_entry(&cache, "a", "//a:a", &[("a.beam", "a")], 10);
+        let root = cache.root.clone();
+        let cache = cache.with_workspace(&root);
+
+        cache.touch("a");
+        cache.touch("a");
+
+        let manifest = cache.manifest("a").unwrap();
+        assert_eq!(1, manifest.workspaces().len());
+        assert!(manifest
+            .workspaces()
+            .contains(&std::fs::canonicalize(&root).unwrap()));
+    }
+
+    #[test]
+    fn removes_entries_of_the_old_cache_layout() {
+        let cache = cache_at("gc-legacy");
+        let legacy = cache.root.join("0123456789abcdef0123456789abcdef01234567");
+        let global = cache
+            .root
+            .join("erlang-0123456789abcdef0123456789abcdef01234567");
+        for dir in &[&legacy, &global] {
+            std::fs::create_dir_all(dir.join("bin")).unwrap();
+            std::fs::write(dir.join("bin/erl"), "erl").unwrap();
+        }
+
+        let report = cache.gc(&live(&[]), 0, true).unwrap();
+        assert_eq!(1, report.removed_legacy_entries);
+        assert_eq!(3, report.reclaimable_bytes);
+        assert!(legacy.exists());
+
+        cache.gc(&live(&[]), 0, false).unwrap();
+        assert!(!legacy.exists());
+        assert!(global.exists());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let cache = cache_at("evict");
+        store_entry(&cache, "a", "//a:a", &[("a.beam", "aaaaaaaaaa")], 30);
+        store_entry(&cache, "b", "//b:b", &[("b.beam", "bbbbbbbbbb")], 20);
+        store_entry(&cache, "c", "//c:c", &[("c.beam", "cccccccccc")], 10);
+        store_entry(&cache, "d", "//d:d", &[("d.beam", "dddddddddd")], 0);
+        // `a` was used by the current build, so it is not evicted
+        cache.memcache.insert("a".to_string(), Label::new("//a:a"));
+
+        let total = cache.stats().unwrap().total_bytes;
+        let entry_size = total / 4;
+        let report = cache.evict(total - entry_size).unwrap();
+
+        assert_eq!(1, report.evicted_entries);
+        assert_eq!(entry_size, report.freed_bytes);
+        assert_eq!(total - entry_size, report.total_bytes);
+        let mut hashes: Vec<String> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.hash)
+            .collect();
+        hashes.sort();
+        assert_eq!(vec!["a", "c", "d"], hashes);
+        assert_eq!(3, cache.blobs.list().unwrap().len());
+    }
+
+    #[test]
+    fn accumulates_hits_and_misses_across_builds() {
+        let cache = cache_at("stats");
+        store_entry(&cache, "small", "//a:small", &[("a.beam", "a")], 0);
+        store_entry(
+            &cache,
+            "large",
+            "//a:large",
+            &[("a.beam", "aaaaa"), ("b.beam", "b")],
+            0,
+        );
+
+        cache.hits.fetch_add(3, Ordering::SeqCst);
+        cache.misses.fetch_add(1, Ordering::SeqCst);
+        cache.save_stats().unwrap();
+        cache.hits.fetch_add(1, Ordering::SeqCst);
+        cache.misses.fetch_add(3, Ordering::SeqCst);
+        cache.save_stats().unwrap();
+
+        let stats = cache.stats().unwrap();
+        assert_eq!(4, stats.hits);
+        assert_eq!(4, stats.misses);
+        assert_eq!(Some(0.5), stats.hit_rate());
+        assert_eq!(2, stats.entries);
+        assert_eq!(3, stats.blobs);
+        let largest: Vec<(String, u64)> = stats
+            .largest_entries
+            .into_iter()
+            .map(|(entry, size)| (entry.hash, size))
+            .collect();
+        assert_eq!(
+            vec![("large".to_string(), 6), ("small".to_string(), 1)],
+            largest
+        );
+    }
+
+    #[test]
+    fn detects_missing_and_tampered_outputs() {
+        let cache = cache_at("verify");
+        store_entry(&cache, "ok", "//a:ok", &[("a.beam", "ok")], 0);
+        store_entry(
+            &cache,
+            "missing",
+            "//a:missing",
+            &[("a.beam", "missing")],
+            0,
+        );
+        store_entry(
+            &cache,
+            "tampered",
+            "//a:tampered",
+            &[("a.beam", "tampered")],
+            0,
+        );
+
+        let missing = cache.manifest("missing").unwrap().outputs()[0].1.clone();
+        cache.blobs.remove(&missing).unwrap();
+        let tampered = cache.manifest("tampered").unwrap().outputs()[0].1.clone();
+        make_writable(&cache.blobs.path(&tampered)).unwrap();
+        std::fs::write(cache.blobs.path(&tampered), "oh no").unwrap();
+
+        let mut corrupted = cache.verify().unwrap();
+        corrupted.sort_by_key(|(entry, _)| entry.hash.clone());
+        let corrupted: Vec<(String, Vec<OutputCorruption>)> = corrupted
+            .into_iter()
+            .map(|(entry, corruptions)| (entry.hash, corruptions))
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    "missing".to_string(),
+                    vec![OutputCorruption::Missing {
+                        path: PathBuf::from("a.beam"),
+                        digest: missing
+                    }]
+                ),
+                (
+                    "tampered".to_string(),
+                    vec![OutputCorruption::Tampered {
+                        path: PathBuf::from("a.beam"),
+                        digest: tampered.clone()
+                    }]
+                ),
+            ],
+            corrupted
+        );
+
+        for (hash, corruptions) in corrupted {
+            cache.discard(&hash, &corruptions).unwrap();
+        }
+        let hashes: Vec<String> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.hash)
+            .collect();
+        assert_eq!(vec!["ok"], hashes);
+        assert!(!cache.blobs.contains(&tampered));
+        assert!(cache.verify().unwrap().is_empty());
+    }
+
+    #[test]
+    fn fails_to_find_outputs_of_missing_entries() {
+        let cache = cache_at("missing-entry");
+        assert!(cache.output_path("nope", Path::new("a.beam")).is_err());
+    }
+
+    /// A remote cache that keeps its bundles in memory.
+    #[derive(Debug, Default)]
+    struct MemoryRemote(std::sync::Mutex<HashMap<String, Vec<u8>>>);
+
+    impl CacheBackend for MemoryRemote {
+        fn name(&self) -> String {
+            "memory".to_string()
+        }
+
+        fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+            Ok(self.0.lock().unwrap().get(hash).cloned())
+        }
+
+        fn put(&self, hash: &str, bundle: &[u8]) -> Result<(), anyhow::Error> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(hash.to_string(), bundle.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ignores_remote_entries_with_invalid_digests() {
+        let remote = Arc::new(MemoryRemote::default());
+        let cache = cache_at("remote-invalid-digest").with_remote(remote.clone(), false);
+        let victim = cache.root.join("victim");
+        std::fs::write(&victim, "secret").unwrap();
+
+        let manifest = cache.root.join("malicious-manifest");
+        std::fs::write(&manifest, "# //a:a\n../../victim  a.beam\n").unwrap();
+        let bundle = pack_bundle(&[(PathBuf::from("manifest"), manifest)]).unwrap();
+        remote.put("hash", &bundle).unwrap();
+
+        assert!(!cache.fetch("hash"));
+        assert_eq!("secret", std::fs::read_to_string(&victim).unwrap());
+        assert!(!cache.manifest_path("hash").exists());
+    }
+
+    #[test]
+    fn keeps_workspaces_out_of_the_remote_cache() {
+        let remote = Arc::new(MemoryRemote::default());
+        let workspace = cache_at("remote-workspaces-a").root.clone();
+        let cache = cache_at("remote-workspaces-a-cache")
+            .with_workspace(&workspace)
+            .with_remote(remote.clone(), true);
+        store_entry(&cache, "hash", "//a:a", &[("a.beam", "a")], 0);
+        cache.upload("hash", &cache.owned(cache.manifest("hash").unwrap()));
+
+        let bundle = remote.get("hash").unwrap().unwrap();
+        let unpacked = cache.root.join("unpacked");
+        unpack_bundle(&bundle, &unpacked).unwrap();
+        let uploaded = CacheManifest::read(&unpacked.join("manifest")).unwrap();
+        assert!(uploaded.workspaces().is_empty());
+        assert_eq!(1, uploaded.outputs().len());
+
+        let manifest = cache.root.join("remote-manifest");
+        std::fs::write(&manifest, "# //b:b\n@ /src/elsewhere\n").unwrap();
+        let bundle = pack_bundle(&[(PathBuf::from("manifest"), manifest)]).unwrap();
+        remote.put("other-hash", &bundle).unwrap();
+
+        let other_workspace = cache_at("remote-workspaces-b").root.clone();
+        let other = cache_at("remote-workspaces-b-cache")
+            .with_workspace(&other_workspace)
+            .with_remote(remote, false);
+        assert!(other.fetch("hash"));
+        assert!(other.fetch("other-hash"));
+        for hash in &["hash", "other-hash"] {
+            let fetched = other.manifest(hash).unwrap();
+            assert_eq!(
+                vec![std::fs::canonicalize(&other_workspace).unwrap()],
+                fetched
+                    .workspaces()
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<PathBuf>>()
+            );
+        }
+    }
+}
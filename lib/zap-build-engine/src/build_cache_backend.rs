@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Context};
+use log::*;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// A CacheBackend is a place outside of the local cache where build outputs
+/// can be shared across machines.
+///
+/// Backends only deal with _bundles_: an archive of all the outputs of a
+/// target, keyed by the target's hash (see `ComputedTarget::hash`). How
+/// bundles are created and unpacked is up to the BuildCache.
+///
+pub trait CacheBackend: std::fmt::Debug + Send + Sync {
+    /// A human-readable name for this backend, used for logging.
+    fn name(&self) -> String;
+
+    /// Fetch the bundle for a given hash, if the backend has one.
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, anyhow::Error>;
+
+    /// Store the bundle for a given hash.
+    fn put(&self, hash: &str, bundle: &[u8]) -> Result<(), anyhow::Error>;
+}
+
+/// A CacheBackend that speaks plain HTTP.
+///
+/// Bundles are fetched with `GET <url>/<hash>.tar.gz` and stored with
+/// `PUT <url>/<hash>.tar.gz`, so any server that can serve and accept static
+/// files (nginx with WebDAV, an S3 bucket, etc) can be used as a remote cache.
+///
+/// A `404 Not Found` is considered a cache miss.
+///
+#[derive(Debug)]
+pub struct HttpCacheBackend {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpCacheBackend {
+    pub fn new(url: &str) -> HttpCacheBackend {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(5))
+            .timeout(Duration::from_secs(120))
+            .build();
+        HttpCacheBackend {
+            url: url.trim_end_matches('/').to_string(),
+            agent,
+        }
+    }
+
+    fn bundle_url(&self, hash: &str) -> String {
+        format!("{}/{}.tar.gz", self.url, hash)
+    }
+}
+
+impl CacheBackend for HttpCacheBackend {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        let url = self.bundle_url(hash);
+        debug!("Fetching cache bundle from {}", &url);
+        match self.agent.get(&url).call() {
+            Ok(response) => {
+                let mut bundle = vec![];
+                response
+                    .into_reader()
+                    .read_to_end(&mut bundle)
+                    .context(format!("Could not read cache bundle from {}", &url))?;
+                Ok(Some(bundle))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(ureq::Error::Status(code, _)) => Err(anyhow!(
+                "Remote cache answered with status {} when fetching {}",
+                code,
+                &url
+            )),
+            Err(err) => Err(anyhow!("Could not fetch {}: {}", &url, err)),
+        }
+    }
+
+    fn put(&self, hash: &str, bundle: &[u8]) -> Result<(), anyhow::Error> {
+        let url = self.bundle_url(hash);
+        debug!("Uploading cache bundle to {}", &url);
+        match self
+            .agent
+            .put(&url)
+            .set("Content-Type", "application/gzip")
+            .send_bytes(bundle)
+        {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(code, _)) => Err(anyhow!(
+                "Remote cache answered with status {} when uploading {}",
+                code,
+                &url
+            )),
+            Err(err) => Err(anyhow!("Could not upload {}: {}", &url, err)),
+        }
+    }
+}
+
+/// Pack a list of files into a gzipped tarball. Every file is given as a pair
+/// of the name it will have in the bundle and its path on disk.
+pub fn pack_bundle(files: &[(PathBuf, PathBuf)]) -> Result<Vec<u8>, anyhow::Error> {
+    let encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    let mut tar = tar::Builder::new(encoder);
+    for (name, path) in files {
+        tar.append_path_with_name(path, name)
+            .context(format!("Could not bundle {:?} as {:?}", path, name))?;
+    }
+    let encoder = tar.into_inner()?;
+    Ok(encoder.finish()?)
+}
+
+/// Unpack a gzipped tarball created with `pack_bundle` into `root`.
+pub fn unpack_bundle(bundle: &[u8], root: &Path) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(root)?;
+    let decoder = flate2::read::GzDecoder::new(bundle);
+    tar::Archive::new(decoder)
+        .unpack(root)
+        .context(format!("Could not unpack cache bundle into {:?}", root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fixture_dir;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Start an in-process HTTP server that stores PUT bodies in memory and
+    /// serves them back on GET.
+    fn start_server() -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cache", server.server_addr());
+        let store: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::new(Mutex::new(HashMap::new()));
+
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let path = request.url().to_string();
+                let response = match request.method() {
+                    tiny_http::Method::Put => {
+                        let mut body = vec![];
+                        request.as_reader().read_to_end(&mut body).unwrap();
+                        store.lock().unwrap().insert(path, body);
+                        tiny_http::Response::from_data(vec![]).with_status_code(201)
+                    }
+                    tiny_http::Method::Get => match store.lock().unwrap().get(&path) {
+                        Some(body) => tiny_http::Response::from_data(body.clone()),
+                        None => tiny_http::Response::from_data(vec![]).with_status_code(404),
+                    },
+                    _ => tiny_http::Response::from_data(vec![]).with_status_code(405),
+                };
+                request.respond(response).unwrap();
+            }
+        });
+
+        url
+    }
+
+    #[test]
+    fn missing_bundles_are_cache_misses() {
+        let backend = HttpCacheBackend::new(&start_server());
+        assert_eq!(None, backend.get("missing-hash").unwrap());
+    }
+
+    #[test]
+    fn fetches_stored_bundles() {
+        let backend = HttpCacheBackend::new(&start_server());
+        backend.put("some-hash", b"bundle contents").unwrap();
+        assert_eq!(
+            Some(b"bundle contents".to_vec()),
+            backend.get("some-hash").unwrap()
+        );
+        assert_eq!(None, backend.get("other-hash").unwrap());
+    }
+
+    #[test]
+    fn fails_when_server_is_unreachable() {
+        let backend = HttpCacheBackend::new("http://127.0.0.1:1/cache");
+        assert!(backend.get("some-hash").is_err());
+    }
+
+    #[test]
+    fn bundles_roundtrip_through_the_backend() {
+        let src = fixture_dir("cache-backend", "roundtrip-src");
+        std::fs::write(src.join("a.beam"), [0xca, 0xfe, 0xba, 0xbe]).unwrap();
+        std::fs::write(src.join("a.app"), "{application, a, []}.").unwrap();
+        let files = vec![
+            (PathBuf::from("a/a.beam"), src.join("a.beam")),
+            (PathBuf::from("a/a.app"), src.join("a.app")),
+        ];
+
+        let backend = HttpCacheBackend::new(&start_server());
+        backend.put("hash", &pack_bundle(&files).unwrap()).unwrap();
+
+        let dst = fixture_dir("cache-backend", "roundtrip-dst");
+        unpack_bundle(&backend.get("hash").unwrap().unwrap(), &dst).unwrap();
+
+        assert_eq!(
+            vec![0xca, 0xfe, 0xba, 0xbe],
+            std::fs::read(dst.join("a/a.beam")).unwrap()
+        );
+        assert_eq!(
+            "{application, a, []}.",
+            std::fs::read_to_string(dst.join("a/a.app")).unwrap()
+        );
+    }
+}
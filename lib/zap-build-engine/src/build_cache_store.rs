@@ -0,0 +1,372 @@
+use super::make_read_only;
+use anyhow::{anyhow, Context};
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use std::collections::BTreeSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// A BlobStore keeps files keyed by the digest of their contents, so that
+/// identical outputs are only stored once, no matter how many targets
+/// produced them.
+///
+/// Blobs are laid out as `<root>/<first 2 chars of digest>/<digest>` to keep
+/// directories from growing too large.
+///
+#[derive(Debug, Clone)]
+pub struct BlobStore {
+    root: PathBuf,
+}
+
+impl BlobStore {
+    pub fn new(root: PathBuf) -> BlobStore {
+        BlobStore { root }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The path where the blob for a given digest lives.
+    pub fn path(&self, digest: &str) -> PathBuf {
+        let fanout = digest.get(0..2).unwrap_or("__");
+        self.root.join(fanout).join(digest)
+    }
+
+    /// Whether `digest` looks like a digest computed by this store: 40
+    /// lowercase hex characters, so it is always safe to use in a path.
+    pub fn is_digest(digest: &str) -> bool {
+        digest.len() == 40
+            && digest
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.path(digest).is_file()
+    }
+
+    /// Move a file into the store, returning its digest.
+    ///
+    /// If a blob with the same contents is already stored, the file is just
+    /// removed.
+    ///
+    /// Blobs are made read-only, since outputs may be hardlinks to them, and
+    /// writing to one of those would corrupt the blob.
+    pub fn insert(&self, file: &Path) -> Result<String, anyhow::Error> {
+        let digest = BlobStore::digest_file(file)
+            .context(format!("Could not compute the digest of {:?}", file))?;
+
+        let blob = self.path(&digest);
+        if blob.is_file() {
+            // NOTE: blobs stored before they were made read-only are fixed up
+            // here, the first time they are used again.
+            make_read_only(&blob).context(format!("Could not make blob {:?} read-only", &blob))?;
+            std::fs::remove_file(file).context(format!(
+                "Could not remove {:?}, already stored as blob {}",
+                file, &digest
+            ))?;
+            return Ok(digest);
+        }
+
+        let blob_dir = blob.parent().unwrap();
+        std::fs::create_dir_all(blob_dir)
+            .context(format!("Could not create blob directory {:?}", &blob_dir))?;
+
+        // NOTE: renaming is atomic, so if two identical files are inserted at
+        // the same time, whichever is moved last wins, and the blob is the
+        // same anyway.
+        std::fs::rename(file, &blob).context(format!(
+            "Could not move {:?} into blob store at {:?}",
+            file, &blob
+        ))?;
+        make_read_only(&blob).context(format!("Could not make blob {:?} read-only", &blob))?;
+
+        Ok(digest)
+    }
+
+    /// List every stored blob, along with its size in bytes.
+    pub fn list(&self) -> Result<Vec<(String, u64)>, anyhow::Error> {
+        let mut blobs = vec![];
+        if !self.root.is_dir() {
+            return Ok(blobs);
+        }
+
+        for fanout in std::fs::read_dir(&self.root)
+            .context(format!("Could not list blob store at {:?}", &self.root))?
+        {
+            let fanout = fanout?;
+            if !fanout.file_type()?.is_dir() {
+                continue;
+            }
+            for blob in std::fs::read_dir(fanout.path())? {
+                let blob = blob?;
+                let metadata = blob.metadata()?;
+                if metadata.is_file() {
+                    blobs.push((
+                        blob.file_name().to_string_lossy().to_string(),
+                        metadata.len(),
+                    ));
+                }
+            }
+        }
+
+        Ok(blobs)
+    }
+
+    pub fn remove(&self, digest: &str) -> Result<(), anyhow::Error> {
+        let blob = self.path(digest);
+        std::fs::remove_file(&blob).context(format!("Could not remove blob {:?}", &blob))
+    }
+
+    /// Rehash a stored blob to check that its contents still match its digest.
+    ///
+    /// Missing blobs are never valid.
+    pub fn verify(&self, digest: &str) -> bool {
+        match BlobStore::digest_file(&self.path(digest)) {
+            Ok(actual) => actual == digest,
+            Err(_) => false,
+        }
+    }
+
+    /// Compute the digest of a file, streaming its contents.
+    pub fn digest_file(path: &Path) -> Result<String, std::io::Error> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha1::new();
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.input(&buf[..read]);
+        }
+        Ok(hasher.result_str())
+    }
+}
+
+/// A CacheManifest maps every output of a cached target to the digest of the
+/// blob holding its contents.
+///
+/// On disk, manifests are plain text with one `<digest>  <path>` line per
+/// output, sorted by path, in the same format that `sha1sum` uses. The label
+/// of the target is kept in a leading `# <label>` line, followed by one
+/// `@ <root>` line for every workspace that built or used the entry.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheManifest {
+    label: Option<String>,
+    workspaces: BTreeSet<PathBuf>,
+    outputs: Vec<(PathBuf, String)>,
+}
+
+impl CacheManifest {
+    pub fn new() -> CacheManifest {
+        CacheManifest::default()
+    }
+
+    pub fn with_label(self, label: String) -> CacheManifest {
+        CacheManifest {
+            label: Some(label),
+            ..self
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn with_workspace(mut self, root: &Path) -> CacheManifest {
+        self.workspaces.insert(root.to_path_buf());
+        self
+    }
+
+    /// This manifest without the workspaces that use it, which only make
+    /// sense on this machine.
+    pub fn without_workspaces(self) -> CacheManifest {
+        CacheManifest {
+            workspaces: BTreeSet::new(),
+            ..self
+        }
+    }
+
+    /// The roots of the workspaces that built or used this entry.
+    pub fn workspaces(&self) -> &BTreeSet<PathBuf> {
+        &self.workspaces
+    }
+
+    pub fn add(&mut self, path: PathBuf, digest: String) {
+        self.outputs.retain(|(p, _)| *p != path);
+        self.outputs.push((path, digest));
+        self.outputs.sort();
+    }
+
+    pub fn outputs(&self) -> &[(PathBuf, String)] {
+        &self.outputs
+    }
+
+    pub fn digest_of(&self, path: &Path) -> Option<&str> {
+        self.outputs
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, digest)| digest.as_str())
+    }
+
+    pub fn parse(contents: &str) -> Result<CacheManifest, anyhow::Error> {
+        let mut manifest = CacheManifest::new();
+        for (n, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(label) = line.strip_prefix("# ") {
+                manifest.label = Some(label.to_string());
+                continue;
+            }
+            if let Some(root) = line.strip_prefix("@ ") {
+                manifest.workspaces.insert(PathBuf::from(root));
+                continue;
+            }
+            let mut parts = line.splitn(2, "  ");
+            match (parts.next(), parts.next()) {
+                (Some(digest), Some(path)) if BlobStore::is_digest(digest) && !path.is_empty() => {
+                    manifest.add(PathBuf::from(path), digest.to_string())
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Malformed manifest entry on line {}: {:?}",
+                        n + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn read(path: &Path) -> Result<CacheManifest, anyhow::Error> {
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Could not read cache manifest at {:?}", path))?;
+        CacheManifest::parse(&contents).context(format!("Invalid cache manifest at {:?}", path))
+    }
+
+    /// Write this manifest to disk.
+    ///
+    /// The manifest is what makes a cache entry visible, so it is written to a
+    /// temporary file first and then moved into place.
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir)
+            .context(format!("Could not create manifest directory {:?}", &dir))?;
+
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, self.to_string())
+            .context(format!("Could not write cache manifest at {:?}", &tmp_path))?;
+        std::fs::rename(&tmp_path, path)
+            .context(format!("Could not move cache manifest into {:?}", path))
+    }
+}
+
+impl std::fmt::Display for CacheManifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "# {}", label)?;
+        }
+        for root in &self.workspaces {
+            writeln!(f, "@ {}", root.to_string_lossy())?;
+        }
+        for (path, digest) in &self.outputs {
+            writeln!(f, "{}  {}", digest, path.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fixture_dir;
+    use crate::make_writable;
+
+    #[test]
+    fn identical_files_are_stored_once() {
+        let dir = fixture_dir("cache-store", "dedupe");
+        let store = BlobStore::new(dir.join("blobs"));
+        std::fs::write(dir.join("a.beam"), [0xca, 0xfe]).unwrap();
+        std::fs::write(dir.join("b.beam"), [0xca, 0xfe]).unwrap();
+
+        let a = store.insert(&dir.join("a.beam")).unwrap();
+        let b = store.insert(&dir.join("b.beam")).unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!("ac3c34dd3b4d1c52245d8e5cad42987b5027ca3d", a);
+        assert_eq!(vec![0xca, 0xfe], std::fs::read(store.path(&a)).unwrap());
+        assert!(std::fs::metadata(store.path(&a))
+            .unwrap()
+            .permissions()
+            .readonly());
+        assert!(!dir.join("a.beam").exists());
+        assert!(!dir.join("b.beam").exists());
+    }
+
+    #[test]
+    fn verifies_blobs_by_rehashing_them() {
+        let dir = fixture_dir("cache-store", "verify");
+        let store = BlobStore::new(dir.join("blobs"));
+        std::fs::write(dir.join("a.beam"), "original").unwrap();
+        let digest = store.insert(&dir.join("a.beam")).unwrap();
+        assert!(store.verify(&digest));
+
+        make_writable(&store.path(&digest)).unwrap();
+        std::fs::write(store.path(&digest), "tampered").unwrap();
+        assert!(!store.verify(&digest));
+
+        std::fs::remove_file(store.path(&digest)).unwrap();
+        assert!(!store.verify(&digest));
+    }
+
+    #[test]
+    fn manifests_roundtrip_through_disk() {
+        let dir = fixture_dir("cache-store", "manifest");
+        let mut manifest = CacheManifest::new()
+            .with_label("//a:a".to_string())
+            .with_workspace(Path::new("/src/b"))
+            .with_workspace(Path::new("/src/a"));
+        let (a, b, c) = ("a".repeat(40), "b".repeat(40), "c".repeat(40));
+        manifest.add(PathBuf::from("a/b.beam"), b.clone());
+        manifest.add(PathBuf::from("a/a.beam"), a.clone());
+        manifest.add(PathBuf::from("a/with  spaces.txt"), c.clone());
+
+        assert_eq!(
+            format!(
+                "# //a:a\n@ /src/a\n@ /src/b\n{}  a/a.beam\n{}  a/b.beam\n{}  a/with  spaces.txt\n",
+                a, b, c
+            ),
+            manifest.to_string()
+        );
+
+        manifest.write(&dir.join("hash")).unwrap();
+        let read = CacheManifest::read(&dir.join("hash")).unwrap();
+        assert_eq!(manifest, read);
+        assert_eq!(Some("//a:a"), read.label());
+        assert!(read.workspaces().contains(Path::new("/src/a")));
+        assert_eq!(Some(b.as_str()), read.digest_of(&PathBuf::from("a/b.beam")));
+        assert_eq!(None, read.digest_of(&PathBuf::from("a/c.beam")));
+    }
+
+    #[test]
+    fn rejects_malformed_manifests() {
+        let contents = format!("{}  a/a.beam\na/b.beam\n", "a".repeat(40));
+        assert_eq!(
+            "Malformed manifest entry on line 2: \"a/b.beam\"",
+            CacheManifest::parse(&contents).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn rejects_digests_that_are_not_hex() {
+        assert!(CacheManifest::parse("../../../home/u/.ssh/id_rsa  a/a.beam\n").is_err());
+        assert!(CacheManifest::parse("aaaa  a/a.beam\n").is_err());
+        assert!(CacheManifest::parse(&format!("{}  a/a.beam\n", "A".repeat(40))).is_err());
+        assert!(CacheManifest::parse(&format!("{}  a/a.beam\n", "a".repeat(40))).is_ok());
+    }
+}
@@ -0,0 +1,38 @@
+use zap_core::Label;
+
+/// BuildProgress is notified by the BuildRunner as targets are looked up in
+/// the cache and built, so the progress of a build can be shown while it is
+/// still running.
+///
+/// Every method is called from the thread running the build, never from the
+/// worker threads, so implementations don't need to be thread-safe.
+///
+pub trait BuildProgress {
+    /// The build is about to start, and will go through `total` targets.
+    fn started(&mut self, _total: usize) {}
+
+    /// A target was found in the cache, and won't be built.
+    fn cached(&mut self, _label: &Label) {}
+
+    /// A target is now being built by a worker.
+    fn building(&mut self, _label: &Label, _mnemonic: &str) {}
+
+    /// A target finished building, either successfully or not.
+    fn built(&mut self, _label: &Label, _succeeded: bool) {}
+
+    /// A target won't be built, since one of its dependencies failed.
+    fn skipped(&mut self, _label: &Label) {}
+
+    /// Called every now and then while targets are being built, even if
+    /// nothing has changed, so elapsed times can be refreshed.
+    fn tick(&mut self) {}
+
+    /// The build is over.
+    fn finished(&mut self) {}
+}
+
+/// A BuildProgress that doesn't show anything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl BuildProgress for NoProgress {}
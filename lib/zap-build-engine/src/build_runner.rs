@@ -1,12 +1,22 @@
-use super::{BuildCache, CacheHitType, Sandbox, ValidationStatus};
+use super::{
+    BuildCache, BuildProgress, BuildScheduler, CacheHitType, Job, NoProgress, Sandbox, Schedule,
+    ScheduleReport, ValidationStatus,
+};
 use anyhow::anyhow;
 use dashmap::DashMap;
-use log::debug;
+use log::{debug, error, warn};
+use petgraph::graph::NodeIndex;
 use petgraph::visit::Topo;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use zap_buildscript::*;
-use zap_core::{Action, DepGraph, Label, Workspace, ZapConfig, ZapWorker};
+use zap_core::{
+    Action, ActionLogs, BuildTimings, ComputeStatus, ComputedTarget, DepGraph, ExecPolicy,
+    Isolation, Label, Workspace, ZapConfig, ZapWorker,
+};
 
 /// The BuildRunner is in charge of actually executing a BuildGraph in the
 /// context of a Workspace, using a given Toolchain, and a given BuildCache.
@@ -21,6 +31,10 @@ use zap_core::{Action, DepGraph, Label, Workspace, ZapConfig, ZapWorker};
 /// 3. Iterate over the BuildGraph, executing buildable rules in a Sandbox,
 ///    and updatting the Cache accordingly
 ///
+/// Targets are scheduled by a BuildScheduler: ready targets are sealed on
+/// the current thread (since the BuildScript runtime can't be shared), and
+/// then built concurrently in up to `jobs` worker threads.
+///
 pub struct BuildRunner {
     /// The workspace in which the build runner will execute.
     workspace: Workspace,
@@ -38,6 +52,93 @@ pub struct BuildRunner {
     bs_ctx: BuildScript,
 
     config: ZapConfig,
+
+    /// The maximum number of targets to build at the same time.
+    jobs: usize,
+
+    /// Whether to keep building targets that don't depend on a failed one.
+    keep_going: bool,
+
+    /// What happened to every target that was looked up in the cache or built.
+    reports: HashMap<NodeIndex, TargetReport>,
+
+    duration: Duration,
+
+    /// Where to show the progress of the build as it runs.
+    progress: Box<dyn BuildProgress>,
+}
+
+/// What happened to a single target during a build.
+#[derive(Debug, Clone)]
+pub struct TargetReport {
+    pub label: Label,
+    pub status: ComputeStatus,
+
+    /// The hash of the target, if it was sealed.
+    pub hash: Option<String>,
+
+    /// How the target was found in the cache, if it was looked up at all.
+    pub cache_hit: Option<CacheHitType>,
+
+    /// How long it took to build the target, or to get it from the cache.
+    pub duration: Duration,
+
+    /// The number of actions of the target.
+    pub actions: usize,
+}
+
+/// What happened to every target in a build.
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport {
+    /// All the targets in the build, in the order they can be built in.
+    pub targets: Vec<TargetReport>,
+
+    /// How long the whole build took.
+    pub duration: Duration,
+}
+
+impl BuildReport {
+    pub fn with_status(&self, statuses: &[ComputeStatus]) -> Vec<&TargetReport> {
+        self.targets
+            .iter()
+            .filter(|target| statuses.contains(&target.status))
+            .collect()
+    }
+
+    /// The report as JSON, for tools that consume `zap build --output json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let targets: Vec<serde_json::Value> = self
+            .targets
+            .iter()
+            .map(|target| {
+                serde_json::json!({
+                    "label": target.label.to_string(),
+                    "hash": target.hash,
+                    "status": match target.status {
+                        ComputeStatus::Uninitialized => "uninitialized",
+                        ComputeStatus::Pending => "pending",
+                        ComputeStatus::CacheHit => "cache_hit",
+                        ComputeStatus::Succeeded => "succeeded",
+                        ComputeStatus::Failed => "failed",
+                        ComputeStatus::Skipped => "skipped",
+                    },
+                    "cache_hit": target.cache_hit.as_ref().map(|hit| match hit {
+                        CacheHitType::Miss => "miss",
+                        CacheHitType::Global => "global",
+                        CacheHitType::Local => "local",
+                        CacheHitType::Remote => "remote",
+                    }),
+                    "duration_ms": target.duration.as_millis() as u64,
+                    "actions": target.actions,
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "duration_ms": self.duration.as_millis() as u64,
+            "targets": targets,
+        })
+    }
 }
 
 impl BuildRunner {
@@ -47,95 +148,411 @@ impl BuildRunner {
             output_map: zap.output_map,
             bs_ctx: zap.bs_ctx,
             dep_graph: zap.dep_graph,
-            build_cache: BuildCache::new(&zap.config),
+            build_cache: BuildCache::new(&zap.config).with_workspace(zap.workspace.root()),
             workspace: zap.workspace,
             config: zap.config,
+            jobs: 1,
+            keep_going: false,
+            reports: HashMap::new(),
+            duration: Duration::default(),
+            progress: Box::new(NoProgress),
+        }
+    }
+
+    pub fn with_jobs(self, jobs: usize) -> BuildRunner {
+        BuildRunner {
+            jobs: jobs.max(1),
+            ..self
         }
     }
 
-    pub fn execute(&mut self, target: &Label) -> Result<u32, anyhow::Error> {
-        self.dep_graph.scoped(&target)?;
+    pub fn with_keep_going(self, keep_going: bool) -> BuildRunner {
+        BuildRunner { keep_going, ..self }
+    }
 
-        let mut targets = 0;
+    pub fn with_progress<P: BuildProgress + 'static>(self, progress: P) -> BuildRunner {
+        BuildRunner {
+            progress: Box::new(progress),
+            ..self
+        }
+    }
 
+    /// Report what happened to every target in the last build.
+    pub fn report(&self) -> BuildReport {
+        let mut targets = vec![];
         let mut walker = Topo::new(&self.dep_graph._inner_graph);
-
         while let Some(idx) = walker.next(&self.dep_graph._inner_graph) {
-            let node = &self.dep_graph.seal_target(
-                idx,
-                &self.action_map,
-                &self.output_map,
-                &mut self.bs_ctx,
-            )?;
-
-            let name = node.label().clone();
-            debug!("About to build {:?}...", name.to_string());
-            debug!("with sources {:?}...", &node.srcs());
-            debug!("with dependencies {:?}...", &node.deps());
-
-            match self.build_cache.is_cached(&node)? {
-                CacheHitType::Global => {
-                    debug!("Skipping {}. Nothing to do.", name.to_string());
-                    continue;
-                }
-                CacheHitType::Local => {
-                    debug!("Skipping {}, but promoting outputs.", name.to_string());
-                    self.build_cache
-                        .promote_outputs(&node, &self.workspace.local_outputs_root)?;
-                    continue;
-                }
-                CacheHitType::Miss => {
-                    debug!("Cache miss! Proceeding to build...");
-                }
+            let node = &self.dep_graph._inner_graph[idx];
+            let report = match self.reports.get(&idx) {
+                Some(report) => TargetReport {
+                    status: node.status().clone(),
+                    ..report.clone()
+                },
+                None => TargetReport {
+                    label: node.label().clone(),
+                    status: node.status().clone(),
+                    hash: None,
+                    cache_hit: None,
+                    duration: Duration::default(),
+                    actions: 0,
+                },
+            };
+            targets.push(report);
+        }
+
+        BuildReport {
+            targets,
+            duration: self.duration,
+        }
+    }
+
+    /// Build these targets, and all of their dependencies.
+    pub fn execute(&mut self, targets: &[Label]) -> Result<u32, anyhow::Error> {
+        let t0 = Instant::now();
+        self.dep_graph.scoped(targets)?;
+
+        let BuildRunner {
+            workspace,
+            dep_graph,
+            build_cache,
+            action_map,
+            output_map,
+            bs_ctx,
+            config,
+            jobs,
+            keep_going,
+            reports,
+            duration,
+            progress,
+        } = self;
+
+        reports.clear();
+
+        let mut timings = BuildTimings::read(&workspace.timings_path()).unwrap_or_else(|err| {
+            warn!("{:?}", err);
+            BuildTimings::new()
+        });
+
+        let scheduler = BuildScheduler::new(&dep_graph._inner_graph, *jobs, *keep_going);
+        progress.started(scheduler.len());
+
+        let mut schedule = TargetSchedule {
+            workspace,
+            dep_graph,
+            build_cache,
+            action_map,
+            output_map,
+            bs_ctx,
+            config,
+            keep_going: *keep_going,
+            reports,
+            timings: &mut timings,
+            progress: progress.as_mut(),
+        };
+        let result = scheduler.run(&mut schedule);
+
+        *duration = t0.elapsed();
+        progress.finished();
+
+        // NOTE: the targets that did finish are saved before looking at the
+        // result, so a failing scheduler doesn't lose their timings or stats.
+        let _span = config.profiler.span("cache", "save_stats_and_evict");
+        if let Err(err) = timings.write(&workspace.timings_path()) {
+            warn!("{:?}", err);
+        }
+
+        if let Err(err) = build_cache.save_stats() {
+            warn!("{:?}", err);
+        }
+
+        if let Some(max_cache_size) = config.max_cache_size {
+            match build_cache.evict(max_cache_size) {
+                Ok(report) => debug!(
+                    "Evicted {} cache entries, freeing {} bytes",
+                    report.evicted_entries, report.freed_bytes
+                ),
+                Err(err) => warn!("Could not evict cache entries: {:?}", err),
             }
+        }
+
+        let ScheduleReport {
+            built: targets,
+            failed,
+            first_error,
+        } = result?;
+        match first_error {
+            Some(_) if *keep_going => Err(anyhow!(
+                "{} {} failed to build",
+                failed,
+                if failed == 1 { "target" } else { "targets" }
+            )),
+            Some(err) => Err(err),
+            None => Ok(targets),
+        }
+    }
+
+    /// Build a single sealed node, returning how many targets were built.
+    fn build_node(
+        node: &ComputedTarget,
+        config: ZapConfig,
+        workspace: &Workspace,
+        build_cache: &BuildCache,
+    ) -> Result<u32, anyhow::Error> {
+        let name = node.label().clone();
+        let profiler = config.profiler.clone();
 
-            let result = if node.target.is_local() {
-                let mut sandbox = Sandbox::for_node(self.config.clone(), &self.workspace, &node);
-                match sandbox.run(&self.build_cache)? {
-                    ValidationStatus::Valid => {
-                        self.build_cache.save(&sandbox)?;
-                        sandbox.clear_sandbox()?;
-                        targets += 1;
-                        Ok(())
-                    }
-                    ValidationStatus::NoOutputs if node.outs().is_empty() => {
-                        sandbox.clear_sandbox()?;
-                        targets += 1;
-                        Ok(())
-                    }
-                    ValidationStatus::NoOutputs => Err(anyhow!(
-                        "Expected {} outputs, but found none.",
-                        node.outs().len()
-                    )),
-                    ValidationStatus::Pending => Err(anyhow!(
-                        "Node {} is somehow still pending...",
-                        &name.to_string()
-                    )),
-                    ValidationStatus::Invalid {
-                        expected_but_missing,
-                        unexpected_but_present,
-                        ..
-                    } => Err(
-                        anyhow!("Node {} expected the following but missing outputs: {:?}\n\ninstead it found the following unexpected outputs: {:?}",
-                            &name.to_string(), expected_but_missing, unexpected_but_present)),
+        if node.target.is_local() {
+            let mut sandbox = Sandbox::for_node(config, workspace, node);
+            let built = sandbox.run(build_cache).and_then(|status| match status {
+                ValidationStatus::Valid => {
+                    let _span = profiler.target_span("cache", "cache_save", &name.to_string());
+                    build_cache.save(&sandbox)?;
+                    Ok(1)
                 }
-            } else {
-                debug!("Building global target...");
-                node.execute(&self.config.archive_root, &self.config.cache_root)
+                ValidationStatus::NoOutputs if node.outs().is_empty() => Ok(1),
+                ValidationStatus::NoOutputs => Err(anyhow!(
+                    "Expected {} outputs, but found none.",
+                    node.outs().len()
+                )),
+                ValidationStatus::Pending => Err(anyhow!(
+                    "Node {} is somehow still pending...",
+                    &name.to_string()
+                )),
+                ValidationStatus::Invalid {
+                    expected_but_missing,
+                    unexpected_but_present,
+                    ..
+                } => Err(
+                    anyhow!("Node {} expected the following but missing outputs: {:?}\n\ninstead it found the following unexpected outputs: {:?}",
+                        &name.to_string(), expected_but_missing, unexpected_but_present)),
+            });
+
+            // NOTE: the sandbox is cleared whether the target was built or
+            // not, so failed builds don't leave it behind.
+            let cleared = sandbox.clear_sandbox();
+            let built = built?;
+            cleared?;
+            Ok(built)
+        } else {
+            debug!("Building global target...");
+            let working_dir = std::env::current_dir()?;
+            let logs = ActionLogs::new(&workspace.logs_root(), &node.hash());
+            // NOTE: global targets, like toolchains, install themselves outside
+            // of any sandbox, so they can't be isolated.
+            let policy = ExecPolicy {
+                isolation: Isolation::None,
+                strict: false,
+                ..workspace.exec_policy().clone()
             };
+            node.execute(
+                &working_dir,
+                &config.archive_root,
+                &config.cache_root,
+                &profiler,
+                &logs,
+                &policy,
+            )
+            .map(|_| 0)
+        }
+    }
+}
+
+/// Seals, looks up in the cache, and builds every target scheduled by a
+/// BuildRunner, keeping track of what happened to them.
+struct TargetSchedule<'a> {
+    workspace: &'a Workspace,
+    dep_graph: &'a mut DepGraph,
+    build_cache: &'a BuildCache,
+    action_map: &'a DashMap<Label, Vec<Action>>,
+    output_map: &'a DashMap<Label, Vec<PathBuf>>,
+    bs_ctx: &'a mut BuildScript,
+    config: &'a ZapConfig,
+    keep_going: bool,
+    reports: &'a mut HashMap<NodeIndex, TargetReport>,
+    timings: &'a mut BuildTimings,
+    progress: &'a mut dyn BuildProgress,
+}
 
-            /*
-            let node = &mut self.build_graph.dep_graph[idx];
-            if result.is_ok() {
+impl<'a> Schedule<'a> for TargetSchedule<'a> {
+    fn prepare(&mut self, idx: NodeIndex) -> Result<Option<Job<'a>>, anyhow::Error> {
+        let config = self.config;
+        let label = self.dep_graph._inner_graph[idx].label().to_string();
+        let node = {
+            let _span = config.profiler.target_span("seal", "seal_target", &label);
+            self.dep_graph
+                .seal_target(idx, self.action_map, self.output_map, self.bs_ctx)?
+                .clone()
+        };
+
+        let name = node.label().clone();
+        debug!("About to build {:?}...", name.to_string());
+        debug!("with sources {:?}...", &node.srcs());
+        debug!("with dependencies {:?}...", &node.deps());
+
+        let t0 = Instant::now();
+        let cache_hit = {
+            let _span = config.profiler.target_span("cache", "cache_lookup", &label);
+            self.build_cache.is_cached(&node)?
+        };
+        let is_cached = match cache_hit {
+            CacheHitType::Global => {
+                debug!("Skipping {}. Nothing to do.", name.to_string());
+                true
+            }
+            CacheHitType::Local | CacheHitType::Remote => {
+                debug!("Skipping {}, but promoting outputs.", name.to_string());
+                let _span = config
+                    .profiler
+                    .target_span("cache", "promote_outputs", &label);
+                self.build_cache
+                    .promote_outputs(&node, &self.workspace.local_outputs_root)?;
+                true
+            }
+            CacheHitType::Miss => {
+                debug!("Cache miss! Proceeding to build...");
+                false
+            }
+        };
+
+        self.reports.insert(
+            idx,
+            TargetReport {
+                label: name.clone(),
+                status: ComputeStatus::Pending,
+                hash: Some(node.hash()),
+                cache_hit: Some(cache_hit),
+                duration: t0.elapsed(),
+                actions: node.actions().len(),
+            },
+        );
+
+        if is_cached {
+            self.progress.cached(&name);
+            self.dep_graph._inner_graph[idx].mark_cache_hit();
+            return Ok(None);
+        }
+
+        self.progress.building(&name, node.target.rule().mnemonic());
+
+        let workspace = self.workspace;
+        let build_cache = self.build_cache;
+        let config = config.clone();
+        Ok(Some(Box::new(move || {
+            let _span = config.profiler.target_span("build", "build", &label);
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                BuildRunner::build_node(&node, config, workspace, build_cache)
+            }))
+            .unwrap_or_else(|_| Err(anyhow!("Building {} panicked!", name.to_string())))
+        })))
+    }
+
+    fn finished(&mut self, idx: NodeIndex, result: &Result<u32, anyhow::Error>, elapsed: Duration) {
+        let node = &mut self.dep_graph._inner_graph[idx];
+        self.progress.built(node.label(), result.is_ok());
+        if let Some(report) = self.reports.get_mut(&idx) {
+            report.duration += elapsed;
+        }
+
+        match result {
+            Ok(_) => {
+                self.timings.record(node.label(), elapsed);
                 node.mark_succeeded();
-            } else {
+            }
+            Err(err) => {
                 node.mark_failed();
+                if self.keep_going {
+                    error!("Could not build {}: {:?}", node.label().to_string(), err);
+                }
             }
-            */
-
-            result?
         }
+    }
+
+    fn skipped(&mut self, idx: NodeIndex) {
+        let node = &mut self.dep_graph._inner_graph[idx];
+        node.mark_skipped();
+        self.progress.skipped(node.label());
+    }
+
+    fn tick(&mut self) {
+        self.progress.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reports_builds_as_json() {
+        let report = BuildReport {
+            targets: vec![
+                TargetReport {
+                    label: Label::new("//a:lib"),
+                    status: ComputeStatus::CacheHit,
+                    hash: Some("abc".to_string()),
+                    cache_hit: Some(CacheHitType::Remote),
+                    duration: Duration::from_millis(12),
+                    actions: 2,
+                },
+                TargetReport {
+                    label: Label::new("//a:bin"),
+                    status: ComputeStatus::Failed,
+                    hash: Some("def".to_string()),
+                    cache_hit: Some(CacheHitType::Miss),
+                    duration: Duration::from_millis(1500),
+                    actions: 1,
+                },
+                TargetReport {
+                    label: Label::new("//a:test"),
+                    status: ComputeStatus::Skipped,
+                    hash: None,
+                    cache_hit: None,
+                    duration: Duration::default(),
+                    actions: 0,
+                },
+            ],
+            duration: Duration::from_secs(2),
+        };
+
+        assert_eq!(
+            json!({
+                "duration_ms": 2000,
+                "targets": [
+                    {
+                        "label": "//a:lib",
+                        "hash": "abc",
+                        "status": "cache_hit",
+                        "cache_hit": "remote",
+                        "duration_ms": 12,
+                        "actions": 2,
+                    },
+                    {
+                        "label": "//a:bin",
+                        "hash": "def",
+                        "status": "failed",
+                        "cache_hit": "miss",
+                        "duration_ms": 1500,
+                        "actions": 1,
+                    },
+                    {
+                        "label": "//a:test",
+                        "hash": null,
+                        "status": "skipped",
+                        "cache_hit": null,
+                        "duration_ms": 0,
+                        "actions": 0,
+                    },
+                ],
+            }),
+            report.to_json()
+        );
 
-        Ok(targets)
+        let failed: Vec<String> = report
+            .with_status(&[ComputeStatus::Failed, ComputeStatus::Skipped])
+            .iter()
+            .map(|target| target.label.to_string())
+            .collect();
+        assert_eq!(vec!["//a:bin", "//a:test"], failed);
     }
 }
@@ -1,4 +1,4 @@
-use super::BuildCache;
+use super::{materialize, BuildCache};
 use anyhow::{anyhow, Context};
 use log::*;
 use std::collections::HashSet;
@@ -18,11 +18,11 @@ use zap_core::*;
 ///   1. ensure the rule outputs are safe
 ///   2. prepare sandbox dir
 ///   3. copy dependences & inputs into sandbox
-///   4. enter sandbox
-///      5. build the node rule
-///      6. validate rule's outputs
-///   7. exit sandbox
+///   4. build the node rule, using the sandbox as its working directory
+///   5. validate rule's outputs
 ///
+/// Note that the sandbox never changes the working directory of the process,
+/// so several sandboxes can build at the same time.
 ///
 pub struct Sandbox<'a> {
     /// The name of this sandbox, not to be confused by the `node.label()`, which
@@ -39,6 +39,12 @@ pub struct Sandbox<'a> {
     /// The outputs created during this build
     outputs: Vec<PathBuf>,
 
+    /// Where the output of every action is logged
+    logs: ActionLogs,
+
+    /// How every action is run
+    policy: ExecPolicy,
+
     status: ValidationStatus,
 
     config: ZapConfig,
@@ -64,8 +70,21 @@ impl<'a> Sandbox<'a> {
     ) -> Sandbox<'a> {
         let root = workspace.sandbox_root().join(node.hash());
         let outputs_root = workspace.local_outputs_root.clone();
+        let logs = ActionLogs::new(&workspace.logs_root(), &node.hash());
+
+        // NOTE: isolated actions can only see the toolchains they depend on
+        let mut policy = workspace.exec_policy().clone();
+        policy.workspace_root = Some(workspace.root().clone());
+        policy.read_only_paths = node
+            .deps()
+            .iter()
+            .flat_map(|dep| dep.root.clone())
+            .collect();
+
         Sandbox {
             name: node.hash(),
+            logs,
+            policy,
             node,
             outputs: vec![],
             root,
@@ -184,12 +203,7 @@ impl<'a> Sandbox<'a> {
         Ok(())
     }
 
-    fn prepare_sandbox_dir(&mut self) -> Result<PathBuf, anyhow::Error> {
-        let current_dir = std::env::current_dir().context(format!(
-            "Could not get the current directory while building sandbox for node {:?}",
-            self.node.label().to_string(),
-        ))?;
-
+    fn prepare_sandbox_dir(&mut self) -> Result<(), anyhow::Error> {
         let _ = std::fs::remove_dir_all(&self.root);
         std::fs::create_dir_all(&self.root)
             .context(format!(
@@ -199,31 +213,42 @@ impl<'a> Sandbox<'a> {
             ))
             .map(|_| ())?;
 
+        // NOTE: actions resolve their paths relative to the sandbox
+        // root, so it has to be absolute to not depend on where zap was called.
+        self.root = std::fs::canonicalize(&self.root).context(format!(
+            "Could not find the absolute path of the sandbox for node {:?} at: {:?}",
+            self.node.label().to_string(),
+            &self.root
+        ))?;
+
         debug!("Created sandbox at: {:?}", &self.root);
 
-        Ok(current_dir)
+        Ok(())
+    }
+
+    /// How dependencies and inputs are put into the sandbox.
+    fn materialization(&self) -> MaterializationStrategy {
+        // NOTE: isolated actions can't see the targets of symlinks pointing
+        // out of the sandbox.
+        match (self.config.materialization, self.policy.isolation) {
+            (MaterializationStrategy::Symlink, Isolation::Namespaces) => {
+                MaterializationStrategy::Hardlink
+            }
+            (strategy, _) => strategy,
+        }
     }
 
     fn copy_dependences(&mut self, build_cache: &BuildCache) -> Result<(), anyhow::Error> {
         // copy all the direct dependency outputs
-        let deps: Vec<(PathBuf, PathBuf)> = self
-            .node
-            .deps()
-            .iter()
-            .flat_map(|dep| {
-                let outs: Vec<(PathBuf, PathBuf)> = dep
-                    .outs
-                    .iter()
-                    .map(|out| {
-                        (
-                            build_cache.absolute_path_by_hash(&dep.hash).join(&out),
-                            self.root.join(&out),
-                        )
-                    })
-                    .collect();
-                outs
-            })
-            .collect();
+        let mut deps: Vec<(PathBuf, PathBuf)> = vec![];
+        for dep in self.node.deps() {
+            for out in &dep.outs {
+                deps.push((
+                    build_cache.output_path(&dep.hash, out)?,
+                    self.root.join(out),
+                ));
+            }
+        }
 
         for (src, dst) in deps {
             if let Some(dst_parent) = &dst.parent() {
@@ -235,7 +260,7 @@ impl<'a> Sandbox<'a> {
                     .map(|_| ())?;
             };
 
-            std::fs::copy(&src, &dst).context(format!(
+            materialize(self.materialization(), &src, &dst).context(format!(
             "When building {:?}, could not copy transitive dependency {:?} into sandbox at {:?}",
             self.node.label().to_string(),
             &src,
@@ -261,7 +286,7 @@ impl<'a> Sandbox<'a> {
                     ))
                     .map(|_| ())?;
             };
-            std::fs::copy(&src, &dst).context(format!(
+            materialize(self.materialization(), src, &dst).context(format!(
                 "When building {:?}, could not copy input {:?} into sandbox at {:?}",
                 self.name.to_string(),
                 &src,
@@ -272,28 +297,6 @@ impl<'a> Sandbox<'a> {
         Ok(())
     }
 
-    fn enter_sandbox(&mut self) -> Result<(), anyhow::Error> {
-        // move into the sandbox
-        std::env::set_current_dir(&self.root).context(format!(
-            "Could not move into the created sandbox at: {:?} when building {:?}",
-            &self.root,
-            self.node.label().to_string()
-        ))?;
-        debug!("Entered sandbox at: {:?}", &self.root);
-        Ok(())
-    }
-
-    fn exit_sandbox(&mut self, working_directory: PathBuf) -> Result<(), anyhow::Error> {
-        // move out of the sandbox
-        std::env::set_current_dir(&working_directory).context(format!(
-            "Could not move out of the created sandbox at: {:?} when building {:?}",
-            &self.root,
-            self.node.label().to_string()
-        ))?;
-        debug!("Exited sandbox at: {:?}", &self.root);
-        Ok(())
-    }
-
     pub fn clear_sandbox(&self) -> Result<(), anyhow::Error> {
         std::fs::remove_dir_all(&self.root).context(format!(
             "Could not clean sandbox for node {:?} at {:?}",
@@ -327,6 +330,13 @@ impl<'a> Sandbox<'a> {
     }
 
     fn promote_outputs(&mut self) -> Result<(), anyhow::Error> {
+        // NOTE: outputs are moved from the sandbox into the cache right after
+        // being promoted, so symlinks to them would be left dangling.
+        let strategy = match self.config.materialization {
+            MaterializationStrategy::Symlink => MaterializationStrategy::Hardlink,
+            strategy => strategy,
+        };
+
         for out in &self.outputs {
             let src = self.root.join(&out);
             let dst = self.outputs_root.join(&out);
@@ -341,7 +351,7 @@ impl<'a> Sandbox<'a> {
                 .map(|_| ())?;
 
             trace!("Promoting {:?} to {:?}", src, dst);
-            std::fs::copy(&src, &dst).context(format!(
+            materialize(strategy, &src, &dst).context(format!(
                 "When promoting outputs for target {:?}, could not copy {:?} into outputs at {:?}",
                 self.node.label().to_string(),
                 &src,
@@ -355,26 +365,44 @@ impl<'a> Sandbox<'a> {
     ///
     /// NOTE(@ostera): wouldn't this be nice as a free monad?
     pub fn run(&mut self, build_cache: &BuildCache) -> Result<ValidationStatus, anyhow::Error> {
-        self.ensure_outputs_are_safe()?;
+        let profiler = self.config.profiler.clone();
+        let label = self.node.label().to_string();
 
-        let working_directory = self.prepare_sandbox_dir()?;
+        self.ensure_outputs_are_safe()?;
 
-        self.copy_dependences(&build_cache)?;
+        {
+            let _span = profiler.target_span("sandbox", "prepare_sandbox", &label);
+            self.prepare_sandbox_dir()?;
+        }
 
-        self.copy_inputs()?;
+        {
+            let _span = profiler.target_span("sandbox", "copy_dependences", &label);
+            self.copy_dependences(build_cache)?;
+        }
 
-        self.enter_sandbox()?;
+        {
+            let _span = profiler.target_span("sandbox", "copy_inputs", &label);
+            self.copy_inputs()?;
+        }
 
-        debug!("Executing build rule...");
-        self.node
-            .execute(&self.config.archive_root, &self.config.cache_root)?;
+        debug!("Executing build rule in sandbox at: {:?}", &self.root);
+        self.node.execute(
+            &self.root,
+            &self.config.archive_root,
+            &self.config.cache_root,
+            &self.config.profiler,
+            &self.logs,
+            &self.policy,
+        )?;
         debug!("Build rule executed successfully.");
 
-        self.exit_sandbox(working_directory)?;
-
-        self.validate_outputs()?;
+        {
+            let _span = profiler.target_span("sandbox", "validate_outputs", &label);
+            self.validate_outputs()?;
+        }
 
         if let ValidationStatus::Valid = self.status {
+            let _span = profiler.target_span("sandbox", "promote_outputs", &label);
             self.promote_outputs()?;
         }
 
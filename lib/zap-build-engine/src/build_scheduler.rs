@@ -0,0 +1,463 @@
+use anyhow::anyhow;
+use crossbeam::channel;
+use petgraph::stable_graph::{NodeIndex, StableDiGraph};
+use petgraph::visit::Topo;
+use petgraph::Direction;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// A Job builds a single node in a worker thread, and returns how many
+/// targets it built.
+pub type Job<'a> = Box<dyn FnOnce() -> Result<u32, anyhow::Error> + Send + 'a>;
+
+/// A Schedule decides how every node in a graph is built, and is told how
+/// building them went.
+///
+/// Every method is called from the thread running the BuildScheduler, never
+/// from the worker threads, so implementations don't need to be thread-safe.
+///
+pub trait Schedule<'a> {
+    /// Prepare a node whose dependencies have all been built, and return the
+    /// Job that builds it, or `None` if there is nothing to build, like when
+    /// the node was found in the cache.
+    fn prepare(&mut self, idx: NodeIndex) -> Result<Option<Job<'a>>, anyhow::Error>;
+
+    /// The Job of a node finished, or the node could not be prepared.
+    fn finished(&mut self, idx: NodeIndex, result: &Result<u32, anyhow::Error>, elapsed: Duration);
+
+    /// A node won't be built, since one of its dependencies failed.
+    fn skipped(&mut self, _idx: NodeIndex) {}
+
+    /// Called every now and then while Jobs are running, even if none of
+    /// them finished.
+    fn tick(&mut self) {}
+}
+
+/// What happened while scheduling a whole graph.
+#[derive(Debug, Default)]
+pub struct ScheduleReport {
+    /// The sum of the targets built by every Job.
+    pub built: u32,
+
+    /// How many nodes failed to be prepared or built.
+    pub failed: usize,
+
+    /// The error of the first node that failed.
+    pub first_error: Option<anyhow::Error>,
+}
+
+/// The BuildScheduler walks a graph whose edges go from a dependency to its
+/// dependents. Every node whose dependencies have all been built is
+/// considered _ready_.
+///
+/// Ready nodes are prepared on the current thread, in the order they became
+/// ready, and their Jobs run in up to `jobs` worker threads at the same time.
+///
+/// A node fails when it can't be prepared, or when its Job fails. Once a node
+/// fails, no other node is prepared, unless `keep_going` is set.
+/// In that case only the nodes that depend on the failed one are skipped,
+/// and everything else is still built.
+///
+#[derive(Debug)]
+pub struct BuildScheduler {
+    jobs: usize,
+
+    keep_going: bool,
+
+    /// The nodes that depend on every node.
+    dependents: HashMap<NodeIndex, Vec<NodeIndex>>,
+
+    /// How many dependencies every node that wasn't built yet is waiting on.
+    waiting_on: HashMap<NodeIndex, usize>,
+
+    ready: VecDeque<NodeIndex>,
+}
+
+impl BuildScheduler {
+    pub fn new<N, E>(graph: &StableDiGraph<N, E>, jobs: usize, keep_going: bool) -> BuildScheduler {
+        let mut dependents = HashMap::new();
+        let mut waiting_on = HashMap::new();
+        let mut ready = VecDeque::new();
+
+        let mut walker = Topo::new(graph);
+        while let Some(idx) = walker.next(graph) {
+            let deps = graph.neighbors_directed(idx, Direction::Incoming).count();
+            if deps == 0 {
+                ready.push_back(idx);
+            }
+            waiting_on.insert(idx, deps);
+            dependents.insert(
+                idx,
+                graph.neighbors_directed(idx, Direction::Outgoing).collect(),
+            );
+        }
+
+        BuildScheduler {
+            jobs: jobs.max(1),
+            keep_going,
+            dependents,
+            waiting_on,
+            ready,
+        }
+    }
+
+    /// The number of nodes to be built.
+    pub fn len(&self) -> usize {
+        self.waiting_on.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiting_on.is_empty()
+    }
+
+    /// Build every node in the graph.
+    pub fn run<'a, S: Schedule<'a>>(
+        mut self,
+        schedule: &mut S,
+    ) -> Result<ScheduleReport, anyhow::Error> {
+        let mut report = ScheduleReport::default();
+        let mut running = 0;
+        let (done_tx, done_rx) = channel::unbounded();
+
+        crossbeam::scope(|scope| -> Result<(), anyhow::Error> {
+            loop {
+                while running < self.jobs && (self.keep_going || report.first_error.is_none()) {
+                    let idx = match self.ready.pop_front() {
+                        Some(idx) => idx,
+                        None => break,
+                    };
+
+                    let t0 = Instant::now();
+                    match schedule.prepare(idx) {
+                        Ok(None) => self.release_dependents(idx),
+                        Ok(Some(job)) => {
+                            let done_tx = done_tx.clone();
+                            scope.spawn(move |_| {
+                                let t0 = Instant::now();
+                                let result = job();
+                                done_tx.send((idx, result, t0.elapsed())).expect(
+                                    "Build results channel was closed before the build finished",
+                                );
+                            });
+                            running += 1;
+                        }
+                        Err(err) => self.finish(schedule, &mut report, idx, Err(err), t0.elapsed()),
+                    }
+                }
+
+                if running == 0 {
+                    break;
+                }
+
+                let (idx, result, elapsed) = match done_rx.recv_timeout(Duration::from_millis(100))
+                {
+                    Ok(done) => done,
+                    Err(channel::RecvTimeoutError::Timeout) => {
+                        schedule.tick();
+                        continue;
+                    }
+                    Err(channel::RecvTimeoutError::Disconnected) => {
+                        panic!("Build results channel was closed before the build finished")
+                    }
+                };
+                running -= 1;
+                self.finish(schedule, &mut report, idx, result, elapsed);
+            }
+            Ok(())
+        })
+        .map_err(|_| anyhow!("A build worker panicked!"))??;
+
+        Ok(report)
+    }
+
+    fn finish<'a, S: Schedule<'a>>(
+        &mut self,
+        schedule: &mut S,
+        report: &mut ScheduleReport,
+        idx: NodeIndex,
+        result: Result<u32, anyhow::Error>,
+        elapsed: Duration,
+    ) {
+        schedule.finished(idx, &result, elapsed);
+        match result {
+            Ok(built) => {
+                report.built += built;
+                self.release_dependents(idx);
+            }
+            Err(err) => {
+                report.failed += 1;
+                if self.keep_going {
+                    self.skip_dependents(schedule, idx);
+                }
+                if report.first_error.is_none() {
+                    report.first_error = Some(err);
+                }
+            }
+        }
+    }
+
+    /// Once a node has been built, every node depending on it has one less
+    /// dependency to wait on. The ones that are not waiting on anything else
+    /// become ready to build.
+    fn release_dependents(&mut self, idx: NodeIndex) {
+        for dependent in &self.dependents[&idx] {
+            if let Some(count) = self.waiting_on.get_mut(dependent) {
+                *count -= 1;
+                if *count == 0 {
+                    self.ready.push_back(*dependent);
+                }
+            }
+        }
+    }
+
+    /// Once a node has failed, nothing depending on it, directly or
+    /// transitively, can be built anymore.
+    fn skip_dependents<'a, S: Schedule<'a>>(&mut self, schedule: &mut S, idx: NodeIndex) {
+        let mut to_skip = self.dependents[&idx].clone();
+        while let Some(dependent) = to_skip.pop() {
+            if self.waiting_on.remove(&dependent).is_none() {
+                continue;
+            }
+            schedule.skipped(dependent);
+            to_skip.extend(&self.dependents[&dependent]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Event {
+        Started(&'static str),
+        Finished(&'static str),
+        Failed(&'static str),
+        Skipped(&'static str),
+    }
+
+    /// Builds every node by sleeping for a bit, and records when it started
+    /// and finished.
+    struct TestSchedule<'g> {
+        graph: &'g StableDiGraph<&'static str, ()>,
+        failing: Vec<&'static str>,
+        unpreparable: Vec<&'static str>,
+        events: Arc<Mutex<Vec<Event>>>,
+        running: Arc<AtomicUsize>,
+        max_running: Arc<AtomicUsize>,
+    }
+
+    impl<'g> TestSchedule<'g> {
+        fn new(graph: &'g StableDiGraph<&'static str, ()>) -> TestSchedule<'g> {
+            TestSchedule {
+                graph,
+                failing: vec![],
+                unpreparable: vec![],
+                events: Arc::new(Mutex::new(vec![])),
+                running: Arc::new(AtomicUsize::new(0)),
+                max_running: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        fn events(&self) -> Vec<Event> {
+            self.events.lock().unwrap().clone()
+        }
+
+        fn position(&self, event: Event) -> usize {
+            self.events()
+                .iter()
+                .position(|e| *e == event)
+                .unwrap_or_else(|| panic!("Expected {:?} in {:?}", event, self.events()))
+        }
+    }
+
+    impl<'a, 'g> Schedule<'a> for TestSchedule<'g> {
+        fn prepare(&mut self, idx: NodeIndex) -> Result<Option<Job<'a>>, anyhow::Error> {
+            let name = self.graph[idx];
+            if self.unpreparable.contains(&name) {
+                return Err(anyhow!("Could not seal {}", name));
+            }
+
+            let fails = self.failing.contains(&name);
+            let events = self.events.clone();
+            let running = self.running.clone();
+            let max_running = self.max_running.clone();
+            Ok(Some(Box::new(move || {
+                events.lock().unwrap().push(Event::Started(name));
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+                running.fetch_sub(1, Ordering::SeqCst);
+                if fails {
+                    Err(anyhow!("{} failed", name))
+                } else {
+                    Ok(1)
+                }
+            })))
+        }
+
+        fn finished(
+            &mut self,
+            idx: NodeIndex,
+            result: &Result<u32, anyhow::Error>,
+            _elapsed: Duration,
+        ) {
+            let name = self.graph[idx];
+            self.events.lock().unwrap().push(match result {
+                Ok(_) => Event::Finished(name),
+                Err(_) => Event::Failed(name),
+            });
+        }
+
+        fn skipped(&mut self, idx: NodeIndex) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(Event::Skipped(self.graph[idx]));
+        }
+    }
+
+    /// Build a graph from `(dependency, dependent)` edges.
+    fn graph(nodes: &[&'static str], edges: &[(&str, &str)]) -> StableDiGraph<&'static str, ()> {
+        let mut graph = StableDiGraph::new();
+        let idx: HashMap<&str, NodeIndex> = nodes
+            .iter()
+            .map(|node| (*node, graph.add_node(*node)))
+            .collect();
+        for (dep, dependent) in edges {
+            graph.add_edge(idx[dep], idx[dependent], ());
+        }
+        graph
+    }
+
+    //     a   b
+    //    / \ /
+    //   c   d
+    //    \ /
+    //     e
+    fn diamond() -> StableDiGraph<&'static str, ()> {
+        graph(
+            &["a", "b", "c", "d", "e"],
+            &[("a", "c"), ("a", "d"), ("b", "d"), ("c", "e"), ("d", "e")],
+        )
+    }
+
+    #[test]
+    fn builds_dependencies_before_their_dependents() {
+        let graph = diamond();
+        for jobs in 1..=4 {
+            let mut schedule = TestSchedule::new(&graph);
+            let report = BuildScheduler::new(&graph, jobs, false)
+                .run(&mut schedule)
+                .unwrap();
+
+            assert_eq!(5, report.built);
+            assert!(report.first_error.is_none());
+            for edge in graph.edge_indices() {
+                let (dep, dependent) = graph.edge_endpoints(edge).unwrap();
+                assert!(
+                    schedule.position(Event::Finished(graph[dep]))
+                        < schedule.position(Event::Started(graph[dependent])),
+                    "{} started before {} finished: {:?}",
+                    graph[dependent],
+                    graph[dep],
+                    schedule.events()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn runs_independent_nodes_at_the_same_time() {
+        let graph = graph(&["a", "b", "c", "d"], &[]);
+        let mut schedule = TestSchedule::new(&graph);
+        BuildScheduler::new(&graph, 4, false)
+            .run(&mut schedule)
+            .unwrap();
+        assert_eq!(4, schedule.max_running.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn never_runs_more_than_jobs_at_the_same_time() {
+        let graph = graph(&["a", "b", "c", "d", "e", "f"], &[]);
+        for jobs in 1..=3 {
+            let mut schedule = TestSchedule::new(&graph);
+            let report = BuildScheduler::new(&graph, jobs, false)
+                .run(&mut schedule)
+                .unwrap();
+            assert_eq!(6, report.built);
+            assert_eq!(jobs, schedule.max_running.load(Ordering::SeqCst));
+        }
+    }
+
+    #[test]
+    fn stops_scheduling_after_a_failure() {
+        let graph = graph(&["a", "b"], &[("a", "b")]);
+        let mut schedule = TestSchedule::new(&graph);
+        schedule.failing = vec!["a"];
+        let report = BuildScheduler::new(&graph, 1, false)
+            .run(&mut schedule)
+            .unwrap();
+
+        assert_eq!(1, report.failed);
+        assert_eq!("a failed", report.first_error.unwrap().to_string());
+        assert_eq!(
+            vec![Event::Started("a"), Event::Failed("a")],
+            schedule.events()
+        );
+    }
+
+    #[test]
+    fn keeps_building_independent_nodes_after_a_failure() {
+        let graph = diamond();
+        let mut schedule = TestSchedule::new(&graph);
+        schedule.failing = vec!["c"];
+        let report = BuildScheduler::new(&graph, 2, true)
+            .run(&mut schedule)
+            .unwrap();
+
+        assert_eq!(3, report.built);
+        assert_eq!(1, report.failed);
+        let events = schedule.events();
+        assert!(events.contains(&Event::Failed("c")));
+        assert!(events.contains(&Event::Skipped("e")));
+        assert!(!events.contains(&Event::Started("e")));
+        for node in &["a", "b", "d"] {
+            assert!(events.contains(&Event::Finished(node)));
+        }
+    }
+
+    #[test]
+    fn nodes_that_cannot_be_prepared_fail_like_any_other() {
+        let graph = diamond();
+        let mut schedule = TestSchedule::new(&graph);
+        schedule.unpreparable = vec!["a"];
+        let report = BuildScheduler::new(&graph, 2, true)
+            .run(&mut schedule)
+            .unwrap();
+
+        assert_eq!(1, report.built);
+        assert_eq!(1, report.failed);
+        assert_eq!("Could not seal a", report.first_error.unwrap().to_string());
+        let events = schedule.events();
+        assert!(events.contains(&Event::Failed("a")));
+        assert!(events.contains(&Event::Finished("b")));
+        for node in &["c", "d", "e"] {
+            assert!(events.contains(&Event::Skipped(node)));
+        }
+        assert_eq!(
+            1,
+            events.iter().filter(|e| **e == Event::Skipped("e")).count()
+        );
+
+        let mut schedule = TestSchedule::new(&graph);
+        schedule.unpreparable = vec!["a"];
+        let report = BuildScheduler::new(&graph, 2, false)
+            .run(&mut schedule)
+            .unwrap();
+        assert_eq!(1, report.failed);
+        assert!(report.first_error.is_some());
+    }
+}
@@ -0,0 +1,15 @@
+use std::path::PathBuf;
+
+/// An empty directory for a test to work in, at
+/// `<temp dir>/zap-build-engine-tests/<suite>/<name>`.
+///
+/// Anything left over from a previous run is removed first.
+pub fn fixture_dir(suite: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join("zap-build-engine-tests")
+        .join(suite)
+        .join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -1,7 +1,19 @@
 mod build_cache;
+mod build_cache_backend;
+mod build_cache_store;
+mod build_progress;
 mod build_runner;
 mod build_sandbox;
+mod build_scheduler;
+#[cfg(test)]
+mod fixtures;
+mod materialize;
 
 pub use self::build_cache::*;
+pub use self::build_cache_backend::*;
+pub use self::build_cache_store::*;
+pub use self::build_progress::*;
 pub use self::build_runner::*;
 pub use self::build_sandbox::*;
+pub use self::build_scheduler::*;
+pub use self::materialize::*;
@@ -0,0 +1,213 @@
+use log::*;
+use std::io;
+use std::path::Path;
+use zap_core::MaterializationStrategy;
+
+/// Put a copy of `src` at `dst` using the given strategy, falling back to a
+/// plain copy if the strategy is not supported.
+///
+/// Anything already at `dst` is removed first. Otherwise writing through a
+/// previously hardlinked file would modify the file it was linked to.
+///
+/// Blobs in the cache are read-only, so hardlinks and symlinks to them are
+/// read-only as well. Copies and reflinks don't share anything with `src`,
+/// so they are always writable.
+///
+pub fn materialize(strategy: MaterializationStrategy, src: &Path, dst: &Path) -> io::Result<()> {
+    match std::fs::remove_file(dst) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => (),
+    }
+
+    let result = match strategy {
+        MaterializationStrategy::Copy => return copy(src, dst),
+        MaterializationStrategy::Hardlink => std::fs::hard_link(src, dst),
+        MaterializationStrategy::Symlink => symlink(src, dst),
+        MaterializationStrategy::Reflink => reflink(src, dst).and_then(|_| make_writable(dst)),
+    };
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            trace!(
+                "Could not {:?} {:?} to {:?}, copying instead: {:?}",
+                strategy,
+                src,
+                dst,
+                err
+            );
+            let _ = std::fs::remove_file(dst);
+            copy(src, dst)
+        }
+    }
+}
+
+fn copy(src: &Path, dst: &Path) -> io::Result<()> {
+    std::fs::copy(src, dst)?;
+    make_writable(dst)
+}
+
+/// Make a file read-only for everyone.
+pub(crate) fn make_read_only(path: &Path) -> io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    if !permissions.readonly() {
+        permissions.set_readonly(true);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+/// Make a file writable by its owner.
+#[cfg(unix)]
+pub(crate) fn make_writable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    if permissions.mode() & 0o200 == 0 {
+        permissions.set_mode(permissions.mode() | 0o200);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub(crate) fn make_writable(path: &Path) -> io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    if permissions.readonly() {
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(std::fs::canonicalize(src)?, dst)
+}
+
+#[cfg(windows)]
+fn symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(std::fs::canonicalize(src)?, dst)
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(src: &Path, dst: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // NOTE: this is the FICLONE ioctl from linux/fs.h
+    const FICLONE: libc::c_ulong = 0x4004_9409;
+
+    let src_file = std::fs::File::open(src)?;
+    let dst_file = std::fs::File::create(dst)?;
+    let result = unsafe { libc::ioctl(dst_file.as_raw_fd(), FICLONE as _, src_file.as_raw_fd()) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    dst_file.set_permissions(src_file.metadata()?.permissions())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &Path, _dst: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "reflinks are not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::fixture_dir;
+    use std::path::PathBuf;
+
+    /// A fixture directory with a `src` file to materialize.
+    fn source_dir(name: &str) -> PathBuf {
+        let dir = fixture_dir("materialize", name);
+        std::fs::write(dir.join("src"), "contents").unwrap();
+        dir
+    }
+
+    #[test]
+    fn materializes_files_with_every_strategy() {
+        for strategy in &[
+            MaterializationStrategy::Copy,
+            MaterializationStrategy::Hardlink,
+            MaterializationStrategy::Symlink,
+            MaterializationStrategy::Reflink,
+        ] {
+            let dir = source_dir(&format!("{:?}", strategy));
+            materialize(*strategy, &dir.join("src"), &dir.join("dst")).unwrap();
+            assert_eq!(
+                "contents",
+                std::fs::read_to_string(dir.join("dst")).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn replaces_existing_files_without_writing_through_them() {
+        let dir = source_dir("replace");
+        materialize(
+            MaterializationStrategy::Hardlink,
+            &dir.join("src"),
+            &dir.join("dst"),
+        )
+        .unwrap();
+
+        std::fs::write(dir.join("new"), "new contents").unwrap();
+        materialize(
+            MaterializationStrategy::Copy,
+            &dir.join("new"),
+            &dir.join("dst"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "new contents",
+            std::fs::read_to_string(dir.join("dst")).unwrap()
+        );
+        assert_eq!(
+            "contents",
+            std::fs::read_to_string(dir.join("src")).unwrap()
+        );
+    }
+
+    #[test]
+    fn symlinks_point_to_the_original_file() {
+        let dir = source_dir("symlink");
+        materialize(
+            MaterializationStrategy::Symlink,
+            &dir.join("src"),
+            &dir.join("dst"),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::canonicalize(dir.join("src")).unwrap(),
+            std::fs::read_link(dir.join("dst")).unwrap()
+        );
+    }
+
+    #[test]
+    fn only_links_to_read_only_files_are_read_only() {
+        for strategy in &[
+            MaterializationStrategy::Copy,
+            MaterializationStrategy::Hardlink,
+            MaterializationStrategy::Reflink,
+        ] {
+            let dir = source_dir(&format!("read-only-{:?}", strategy));
+            make_read_only(&dir.join("src")).unwrap();
+            materialize(*strategy, &dir.join("src"), &dir.join("dst")).unwrap();
+
+            let shares_src = *strategy == MaterializationStrategy::Hardlink;
+            assert_eq!(
+                shares_src,
+                std::fs::metadata(dir.join("dst"))
+                    .unwrap()
+                    .permissions()
+                    .readonly(),
+                "{:?}",
+                strategy
+            );
+        }
+    }
+}
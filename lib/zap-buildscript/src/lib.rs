@@ -27,7 +27,7 @@ pub struct BuildScript {
 }
 
 impl BuildScript {
-    pub fn new() -> BuildScript {
+    pub fn new() -> Result<BuildScript, error::LoadError> {
         let rt_options = deno_core::RuntimeOptions {
             module_loader: Some(Rc::new(deno_core::FsModuleLoader)),
             ..Default::default()
@@ -35,7 +35,11 @@ impl BuildScript {
 
         let runtime = deno_core::JsRuntime::new(rt_options);
 
-        BuildScript { runtime }
+        Ok(BuildScript { runtime })
+    }
+
+    pub async fn load(&mut self, module: PathBuf) -> Result<deno_core::ModuleId, error::LoadError> {
+        self.load_from_path(module).await
     }
 
     pub async fn load_from_str(
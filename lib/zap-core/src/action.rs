@@ -1,8 +1,134 @@
 use anyhow::*;
 use log::*;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+/// The version of the canonical encoding of Actions.
+///
+/// Action encodings are part of every target hash, so this must be bumped
+/// whenever `Action::encode` changes to make sure old cache entries are not
+/// reused.
+///
+pub const ACTION_ENCODING_VERSION: u32 = 3;
+
+/// The PATH exec actions run with, unless they set their own or inherit it.
+#[cfg(not(target_os = "windows"))]
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// The variables every process on Windows needs to even start.
+#[cfg(target_os = "windows")]
+const DEFAULT_INHERITED_ENV: &[&str] = &["COMSPEC", "PATH", "PATHEXT", "SYSTEMROOT", "TEMP", "TMP"];
+
+#[cfg(not(target_os = "windows"))]
+const DEFAULT_INHERITED_ENV: &[&str] = &[];
+
+/// How isolated exec actions are from the rest of the system.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Isolation {
+    /// Actions run as regular processes, within their sandbox directory.
+    #[default]
+    None,
+
+    /// Actions run in Linux namespaces, where they can only see their
+    /// sandbox, the roots of their toolchains, and a read-only view of the
+    /// system. See `NamespaceJail`.
+    Namespaces,
+}
+
+impl std::str::FromStr for Isolation {
+    type Err = anyhow::Error;
+
+    fn from_str(isolation: &str) -> Result<Isolation, anyhow::Error> {
+        match isolation {
+            "none" => Ok(Isolation::None),
+            "namespaces" => Ok(Isolation::Namespaces),
+            _ => Err(anyhow!(
+                "Unknown sandbox strategy {:?}, expected one of: none, namespaces",
+                isolation
+            )),
+        }
+    }
+}
+
+/// How exec actions are run within a workspace.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecPolicy {
+    pub limits: ActionLimits,
+
+    pub isolation: Isolation,
+
+    /// Whether isolated actions can access the network.
+    pub allow_network: bool,
+
+    /// The paths isolated actions can read, besides their own sandbox.
+    pub read_only_paths: Vec<PathBuf>,
+
+    /// Whether to fail actions that read files from the workspace that are
+    /// not in their sandbox, and so were never declared.
+    ///
+    /// Only enforced on Linux, and not needed for isolated actions, since
+    /// they can't see the workspace at all.
+    ///
+    /// Only files opened with `open`, `openat` or `openat2` are checked, so
+    /// files that are only executed, stat-ed, read as links or mapped into
+    /// memory are not reported.
+    pub strict: bool,
+
+    /// The root of the workspace, that strict actions may not read from.
+    pub workspace_root: Option<PathBuf>,
+
+    /// The environment variables actions inherit from zap.
+    ///
+    /// Every other variable is cleared, so builds don't depend on who runs
+    /// them. Inherited values are not part of the target hashes.
+    pub inherit_env: Vec<String>,
+}
+
+/// The limits every action runs under.
+///
+/// The timeout applies to every action that doesn't declare its own, while
+/// the resource limits are only enforced on Linux, where they are applied to
+/// the spawned processes with `setrlimit`.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ActionLimits {
+    /// How long an action may run before it is killed.
+    pub timeout: Option<Duration>,
+
+    /// How many seconds of CPU time an action may use.
+    pub cpu_time: Option<u64>,
+
+    /// How many bytes of address space an action may use.
+    pub address_space: Option<u64>,
+
+    /// How many files an action may have open at the same time.
+    pub open_files: Option<u64>,
+}
+
+impl ActionLimits {
+    pub fn has_resource_limits(&self) -> bool {
+        self.cpu_time.is_some() || self.address_space.is_some() || self.open_files.is_some()
+    }
+}
+
+impl std::fmt::Display for ActionLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut limits = vec![];
+        if let Some(cpu_time) = self.cpu_time {
+            limits.push(format!("{}s of CPU time", cpu_time));
+        }
+        if let Some(address_space) = self.address_space {
+            limits.push(format!("{} bytes of address space", address_space));
+        }
+        if let Some(open_files) = self.open_files {
+            limits.push(format!("{} open files", open_files));
+        }
+        write!(f, "{}", limits.join(", "))
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Action {
@@ -25,18 +151,98 @@ impl Action {
             cmd,
             args: vec![],
             cwd: None,
+            env: BTreeMap::new(),
+            timeout: None,
         }
     }
 
-    pub fn run(self) -> Result<(), anyhow::Error> {
+    /// A stable, canonical encoding of this action, used for hashing.
+    ///
+    /// Every field is written in a fixed order, and every variable-length
+    /// value is prefixed by its length, so no two different actions can
+    /// have the same encoding.
+    ///
+    /// Timeouts are left out, since they don't change what an action
+    /// produces.
+    ///
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend_from_slice(&ACTION_ENCODING_VERSION.to_be_bytes());
         match self {
-            Action::Exec(e) => e.run(),
-            Action::Copy(e) => e.run(),
-            Action::WriteFile(e) => e.run(),
+            Action::Exec(e) => {
+                encode_bytes(&mut buf, b"exec");
+                encode_path(&mut buf, &e.cmd);
+                buf.extend_from_slice(&(e.args.len() as u64).to_be_bytes());
+                for arg in &e.args {
+                    encode_bytes(&mut buf, arg.as_bytes());
+                }
+                match &e.cwd {
+                    None => buf.push(0),
+                    Some(cwd) => {
+                        buf.push(1);
+                        encode_path(&mut buf, cwd);
+                    }
+                }
+                buf.extend_from_slice(&(e.env.len() as u64).to_be_bytes());
+                for (key, value) in &e.env {
+                    encode_bytes(&mut buf, key.as_bytes());
+                    encode_bytes(&mut buf, value.as_bytes());
+                }
+            }
+            Action::Copy(c) => {
+                encode_bytes(&mut buf, b"copy");
+                encode_path(&mut buf, &c.src);
+                encode_path(&mut buf, &c.dst);
+            }
+            Action::WriteFile(w) => {
+                encode_bytes(&mut buf, b"write_file");
+                encode_bytes(&mut buf, w.contents.as_bytes());
+                encode_path(&mut buf, &w.dst);
+            }
+        }
+        buf
+    }
+
+    /// Run this action using `root` as its working directory.
+    ///
+    /// Any relative path in the action is resolved relative to `root`, so
+    /// the working directory of the current process is never changed.
+    ///
+    /// Everything the action writes to stdout or stderr is saved to `log`.
+    ///
+    pub fn run(self, root: &Path, log: &Path, policy: &ExecPolicy) -> Result<(), anyhow::Error> {
+        match self {
+            Action::Exec(e) => e.run(root, log, policy),
+            Action::Copy(e) => e.run(root),
+            Action::WriteFile(e) => e.run(root),
         }
     }
 }
 
+fn encode_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Paths are encoded as their raw bytes, so paths that aren't valid UTF-8
+/// don't end up with the same encoding.
+#[cfg(unix)]
+fn encode_path(buf: &mut Vec<u8>, path: &Path) {
+    use std::os::unix::ffi::OsStrExt;
+    encode_bytes(buf, path.as_os_str().as_bytes());
+}
+
+#[cfg(windows)]
+fn encode_path(buf: &mut Vec<u8>, path: &Path) {
+    use std::os::windows::ffi::OsStrExt;
+    let bytes: Vec<u8> = path
+        .as_os_str()
+        .encode_wide()
+        .flat_map(|unit| unit.to_be_bytes().to_vec())
+        .collect();
+    encode_bytes(buf, &bytes);
+}
+
 #[derive(Debug, Clone)]
 pub struct WriteFileAction {
     contents: String,
@@ -44,11 +250,12 @@ pub struct WriteFileAction {
 }
 
 impl WriteFileAction {
-    fn run(self) -> Result<(), anyhow::Error> {
-        if let Some(parent) = self.dst.parent() {
+    fn run(self, root: &Path) -> Result<(), anyhow::Error> {
+        let dst = root.join(&self.dst);
+        if let Some(parent) = dst.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        std::fs::write(&self.dst, &self.contents)
+        std::fs::write(&dst, &self.contents)
             .map(|_| ())
             .context(format!("Could not run action {:#?}", &self))
     }
@@ -61,11 +268,12 @@ pub struct CopyAction {
 }
 
 impl CopyAction {
-    fn run(self) -> Result<(), anyhow::Error> {
-        if let Some(parent) = self.dst.parent() {
+    fn run(self, root: &Path) -> Result<(), anyhow::Error> {
+        let dst = root.join(&self.dst);
+        if let Some(parent) = dst.parent() {
             std::fs::create_dir_all(parent)?;
         }
-        std::fs::copy(&self.src, &self.dst)
+        std::fs::copy(root.join(&self.src), &dst)
             .map(|_| ())
             .context(format!("Could not run action {:#?}", &self))
     }
@@ -76,42 +284,387 @@ pub struct ExecAction {
     cmd: PathBuf,
     args: Vec<String>,
     cwd: Option<PathBuf>,
+    env: BTreeMap<String, String>,
+    timeout: Option<Duration>,
 }
 
 impl ExecAction {
-    fn run(self) -> Result<(), anyhow::Error> {
-        let mut cmd = Command::new(&self.cmd);
-        cmd.stdout(Stdio::piped()).args(&self.args);
-        if let Some(cwd) = self.cwd {
-            cmd.current_dir(cwd);
-        }
+    fn run(self, root: &Path, log: &Path, policy: &ExecPolicy) -> Result<(), anyhow::Error> {
+        let limits = &policy.limits;
+        // NOTE: stdout and stderr share the same file, so the log keeps
+        // the order in which they were written.
+        let log_file =
+            std::fs::File::create(log).context(format!("Could not create log at {:?}", log))?;
+        let mut cmd = Command::new(ExecAction::resolve_cmd(&self.cmd, root));
+        cmd.stdout(Stdio::from(log_file.try_clone()?))
+            .stderr(Stdio::from(log_file))
+            .args(&self.args)
+            .env_clear()
+            .envs(ExecAction::environment(
+                &self.env,
+                &policy.inherit_env,
+                |key| std::env::var(key).ok(),
+            ));
+        match &self.cwd {
+            Some(cwd) => cmd.current_dir(root.join(cwd)),
+            None => cmd.current_dir(root),
+        };
+
+        ExecAction::set_resource_limits(&mut cmd, limits);
+        let jail_root = ExecAction::isolate(&mut cmd, root, &self.cwd, policy)?;
+        let traced = ExecAction::trace_reads(&mut cmd, policy);
 
         trace!("Executing {:#?}", &cmd,);
 
-        let output = cmd
-            .output()
-            .context(format!("Could not spawn {:?}", self.cmd))?;
+        let mut child = cmd.spawn().context(format!(
+            "Could not spawn {:?}. Actions run with a clean environment, so if it needs to be found in your PATH, add PATH to actions.inherit_env in your Workspace.toml",
+            self.cmd
+        ))?;
+
+        let timeout = self.timeout.or(limits.timeout);
+        let mut reads = BTreeSet::new();
+        let status = match timeout {
+            _ if traced => ExecAction::wait_traced(&child, timeout, &mut reads),
+            None => child.wait().map(Some),
+            Some(timeout) => ExecAction::wait_timeout(&mut child, timeout),
+        };
+        if let (Ok(None), false) = (&status, traced) {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if let Some(jail_root) = jail_root {
+            let _ = std::fs::remove_dir(jail_root);
+        }
+        let status = status?.with_context(|| {
+            format!(
+                "{:?} timed out after {}s",
+                self.cmd,
+                timeout.unwrap_or_default().as_secs_f64()
+            )
+        })?;
 
         trace!(
-            "Executed {:?} with status code: {}",
+            "Executed {:?} with status code: {:?}",
             &self.cmd,
-            output.status.code().unwrap()
+            status.code()
         );
 
-        if output.status.success() {
-            Ok(())
+        if status.success() {
+            return ExecAction::check_reads(&self.cmd, root, policy, &reads);
+        }
+
+        if let Ok(output) = std::fs::read(log) {
+            std::io::stderr().write_all(&output).unwrap();
+        }
+
+        if limits.cpu_time.is_some() && ExecAction::exceeded_cpu_time(&status) {
+            Err(anyhow!(
+                "{:?} was killed after using up its CPU time limit of {}s",
+                self.cmd,
+                limits.cpu_time.unwrap_or_default()
+            ))
+        } else if limits.has_resource_limits() {
+            Err(anyhow!(
+                "Error running {:?}, which was limited to {}",
+                self.cmd,
+                limits
+            ))
         } else {
-            std::io::stdout().write_all(&output.stdout).unwrap();
-            std::io::stderr().write_all(&output.stderr).unwrap();
             Err(anyhow!("Error running {:?}", self.cmd))
         }
     }
 
+    /// The environment an action runs with: the inherited variables that are
+    /// set in the `parent` environment, overridden by the ones the action
+    /// declared.
+    fn environment(
+        env: &BTreeMap<String, String>,
+        inherit_env: &[String],
+        parent: impl Fn(&str) -> Option<String>,
+    ) -> BTreeMap<String, String> {
+        let mut environment = BTreeMap::new();
+
+        #[cfg(not(target_os = "windows"))]
+        environment.insert("PATH".to_string(), DEFAULT_PATH.to_string());
+
+        let inherited = DEFAULT_INHERITED_ENV
+            .iter()
+            .map(|key| key.to_string())
+            .chain(inherit_env.iter().cloned());
+        for key in inherited {
+            if let Some(value) = parent(&key) {
+                environment.insert(key, value);
+            }
+        }
+
+        environment.extend(env.clone());
+        environment
+    }
+
+    /// Set up the command to have every file it reads traced, if the policy
+    /// asks for it, returning whether it will be.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn trace_reads(cmd: &mut Command, policy: &ExecPolicy) -> bool {
+        if !policy.strict || policy.isolation == Isolation::Namespaces {
+            return false;
+        }
+        super::ReadTracer::trace(cmd);
+        true
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    fn trace_reads(_cmd: &mut Command, policy: &ExecPolicy) -> bool {
+        if policy.strict {
+            warn!("Strict mode is only supported on Linux, ignoring it.");
+        }
+        false
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn wait_traced(
+        child: &std::process::Child,
+        timeout: Option<Duration>,
+        reads: &mut BTreeSet<PathBuf>,
+    ) -> std::io::Result<Option<ExitStatus>> {
+        let mut tracer = super::ReadTracer::new(child.id());
+        let status = tracer.wait(timeout);
+        *reads = tracer.reads().clone();
+        status
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    fn wait_traced(
+        _child: &std::process::Child,
+        _timeout: Option<Duration>,
+        _reads: &mut BTreeSet<PathBuf>,
+    ) -> std::io::Result<Option<ExitStatus>> {
+        unreachable!("reads are only traced on Linux")
+    }
+
+    /// Make sure an action didn't read any file from the workspace outside
+    /// of its sandbox. Those files are not part of the hash of the target,
+    /// so changing them would not cause it to be rebuilt.
+    fn check_reads(
+        cmd: &Path,
+        root: &Path,
+        policy: &ExecPolicy,
+        reads: &BTreeSet<PathBuf>,
+    ) -> Result<(), anyhow::Error> {
+        let workspace_root = match &policy.workspace_root {
+            Some(workspace_root) if !reads.is_empty() => {
+                std::fs::canonicalize(workspace_root).unwrap_or_else(|_| workspace_root.clone())
+            }
+            _ => return Ok(()),
+        };
+
+        let undeclared: Vec<String> = reads
+            .iter()
+            .filter(|path| path.is_file())
+            .map(|path| {
+                // NOTE: only the parent is resolved, since inputs may be
+                // symlinks from the sandbox into the workspace.
+                match (path.parent(), path.file_name()) {
+                    (Some(dir), Some(name)) => std::fs::canonicalize(dir)
+                        .map(|dir| dir.join(name))
+                        .unwrap_or_else(|_| path.clone()),
+                    _ => path.clone(),
+                }
+            })
+            .filter(|path| !path.starts_with(root))
+            .flat_map(|path| {
+                path.strip_prefix(&workspace_root)
+                    .map(|path| format!("    {}", path.display()))
+                    .ok()
+            })
+            .collect();
+
+        if undeclared.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{:?} read files that are not srcs of its target or outputs of its dependencies:\n\n{}\n\nDeclare them as srcs, so the target is rebuilt when they change.",
+                cmd,
+                undeclared.join("\n")
+            ))
+        }
+    }
+
+    /// Wait for a child to finish, for up to `timeout`. If it is still
+    /// running by then, it is left running and `None` is returned.
+    fn wait_timeout(
+        child: &mut std::process::Child,
+        timeout: Duration,
+    ) -> std::io::Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + timeout;
+        let mut interval = Duration::from_millis(1);
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(interval.min(deadline - now));
+            interval = (interval * 2).min(Duration::from_millis(50));
+        }
+    }
+
+    /// Set up the command to run in a NamespaceJail, if the policy asks for
+    /// it, returning the directory used as the root of the jail.
+    #[cfg(target_os = "linux")]
+    fn isolate(
+        cmd: &mut Command,
+        root: &Path,
+        cwd: &Option<PathBuf>,
+        policy: &ExecPolicy,
+    ) -> Result<Option<PathBuf>, anyhow::Error> {
+        use std::os::unix::process::CommandExt;
+
+        if policy.isolation != Isolation::Namespaces {
+            return Ok(None);
+        }
+
+        // NOTE: the jail root is only mounted over within the jail, so from
+        // outside it is just an empty directory next to the sandbox.
+        let mut jail_root = root.as_os_str().to_os_string();
+        jail_root.push(".jail");
+        let jail_root = PathBuf::from(jail_root);
+
+        let cwd = match cwd {
+            Some(cwd) => root.join(cwd),
+            None => root.to_path_buf(),
+        };
+        let jail = super::NamespaceJail::new(
+            &jail_root,
+            root,
+            &policy.read_only_paths,
+            &cwd,
+            policy.allow_network,
+        )
+        .context(format!("Could not prepare jail at {:?}", &jail_root))?;
+
+        unsafe {
+            cmd.pre_exec(move || jail.enter());
+        }
+
+        Ok(Some(jail_root))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn isolate(
+        _cmd: &mut Command,
+        _root: &Path,
+        _cwd: &Option<PathBuf>,
+        policy: &ExecPolicy,
+    ) -> Result<Option<PathBuf>, anyhow::Error> {
+        if policy.isolation == Isolation::Namespaces {
+            warn!("Namespace sandboxing is only supported on Linux, ignoring it.");
+        }
+        Ok(None)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_resource_limits(cmd: &mut Command, limits: &ActionLimits) {
+        use std::os::unix::process::CommandExt;
+
+        if !limits.has_resource_limits() {
+            return;
+        }
+
+        let limits = *limits;
+        let set = |resource, soft: u64, hard: u64| {
+            let limit = libc::rlimit {
+                rlim_cur: soft as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(resource, &limit) } == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        };
+
+        // NOTE: this runs in the forked child right before exec, so it must
+        // not allocate or lock anything.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(cpu_time) = limits.cpu_time {
+                    // the hard limit is a little higher so the process gets a
+                    // SIGXCPU we can recognize before being killed.
+                    set(libc::RLIMIT_CPU, cpu_time, cpu_time + 1)?;
+                }
+                if let Some(address_space) = limits.address_space {
+                    set(libc::RLIMIT_AS, address_space, address_space)?;
+                }
+                if let Some(open_files) = limits.open_files {
+                    set(libc::RLIMIT_NOFILE, open_files, open_files)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_resource_limits(_cmd: &mut Command, limits: &ActionLimits) {
+        if limits.has_resource_limits() {
+            warn!("Resource limits for actions are only supported on Linux, ignoring them.");
+        }
+    }
+
+    /// Only a SIGXCPU is taken as a CPU overrun, since a SIGKILL could have
+    /// been sent by anything else (like the OOM killer). Processes that ignore
+    /// the SIGXCPU are still killed at the hard limit, and reported with the
+    /// rest of their limits.
+    #[cfg(target_os = "linux")]
+    fn exceeded_cpu_time(status: &ExitStatus) -> bool {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal() == Some(libc::SIGXCPU)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn exceeded_cpu_time(_status: &ExitStatus) -> bool {
+        false
+    }
+
+    /// Commands given as a relative path (like `./configure`) are resolved
+    /// relative to the working directory, while bare command names (like
+    /// `erlc`) are left for the system to find in the `PATH`.
+    fn resolve_cmd(cmd: &Path, root: &Path) -> PathBuf {
+        if cmd.is_relative() && cmd.components().count() > 1 {
+            root.join(cmd)
+        } else {
+            cmd.to_path_buf()
+        }
+    }
+
     pub fn cwd(&mut self, cwd: &PathBuf) -> &mut ExecAction {
         self.cwd = Some(cwd.to_path_buf());
         self
     }
 
+    pub fn env(&mut self, key: &str, value: &str) -> &mut ExecAction {
+        self.env.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn timeout(&mut self, timeout: Duration) -> &mut ExecAction {
+        self.timeout = Some(timeout);
+        self
+    }
+
     pub fn args(&mut self, args: &[&str]) -> &mut ExecAction {
         for arg in args {
             self.args.push(arg.to_string());
@@ -123,3 +676,344 @@ impl ExecAction {
         Action::Exec(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::digest::Digest;
+    use crypto::sha1::Sha1;
+
+    fn hash(action: &Action) -> String {
+        let mut hasher = Sha1::new();
+        hasher.input(&action.encode());
+        hasher.result_str()
+    }
+
+    #[test]
+    fn encodes_exec_actions_stably() {
+        let mut action = Action::exec(PathBuf::from("/usr/bin/erlc"));
+        action.args(&["-o", "a", "--", "a/a.erl"]);
+        assert_eq!(
+            "0922e06c244ba45be498cef4bc36696d95f20c31",
+            hash(&action.build())
+        );
+    }
+
+    #[test]
+    fn encodes_copy_actions_stably() {
+        let action = Action::copy(PathBuf::from("a/a.app.src"), PathBuf::from("a/a.app"));
+        assert_eq!("1430047e6282907f19a1700123b8b76242ccb34c", hash(&action));
+    }
+
+    #[test]
+    fn encodes_write_file_actions_stably() {
+        let action = Action::write_file("hello".to_string(), PathBuf::from("a/hello.txt"));
+        assert_eq!("9b9c37bff414da3f7a530f38d2af294f42cf9c5a", hash(&action));
+    }
+
+    #[test]
+    fn distinguishes_argument_boundaries() {
+        let mut a = Action::exec(PathBuf::from("erlc"));
+        a.args(&["ab", "c"]);
+        let mut b = Action::exec(PathBuf::from("erlc"));
+        b.args(&["a", "bc"]);
+        assert_ne!(hash(&a.build()), hash(&b.build()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn logs_stdout_and_stderr_of_exec_actions() {
+        let root = std::env::temp_dir().join("zap-core-action-run");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut action = Action::exec(PathBuf::from("sh"));
+        action.args(&["-c", "echo out; echo err >&2"]);
+        action
+            .build()
+            .run(&root, &root.join("0.log"), &ExecPolicy::default())
+            .unwrap();
+
+        assert_eq!(
+            "out\nerr\n",
+            std::fs::read_to_string(root.join("0.log")).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_actions_that_time_out() {
+        let root = std::env::temp_dir().join("zap-core-action-timeout");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let mut action = Action::exec(PathBuf::from("sleep"));
+        action.args(&["10"]).timeout(Duration::from_millis(100));
+        let t0 = Instant::now();
+        let err = action
+            .build()
+            .run(&root, &root.join("0.log"), &ExecPolicy::default())
+            .unwrap_err();
+
+        assert!(t0.elapsed() < Duration::from_secs(5));
+        assert_eq!("\"sleep\" timed out after 0.1s", err.to_string());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn actions_fall_back_to_the_default_timeout() {
+        let root = std::env::temp_dir().join("zap-core-action-default-timeout");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let policy = ExecPolicy {
+            limits: ActionLimits {
+                timeout: Some(Duration::from_millis(100)),
+                ..ActionLimits::default()
+            },
+            ..ExecPolicy::default()
+        };
+        let mut action = Action::exec(PathBuf::from("sleep"));
+        action.args(&["10"]);
+        let err = action
+            .build()
+            .run(&root, &root.join("0.log"), &policy)
+            .unwrap_err();
+        assert_eq!("\"sleep\" timed out after 0.1s", err.to_string());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn names_the_cpu_time_limit_when_it_is_hit() {
+        let root = std::env::temp_dir().join("zap-core-action-cpu-time");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let policy = ExecPolicy {
+            limits: ActionLimits {
+                cpu_time: Some(1),
+                ..ActionLimits::default()
+            },
+            ..ExecPolicy::default()
+        };
+        let mut action = Action::exec(PathBuf::from("sh"));
+        action.args(&["-c", "while :; do :; done"]);
+        let err = action
+            .build()
+            .run(&root, &root.join("0.log"), &policy)
+            .unwrap_err();
+        assert_eq!(
+            "\"sh\" was killed after using up its CPU time limit of 1s",
+            err.to_string()
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn does_not_blame_the_cpu_time_limit_for_other_kills() {
+        let root = std::env::temp_dir().join("zap-core-action-cpu-time-kill");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        let policy = ExecPolicy {
+            limits: ActionLimits {
+                cpu_time: Some(10),
+                ..ActionLimits::default()
+            },
+            ..ExecPolicy::default()
+        };
+        let mut action = Action::exec(PathBuf::from("sh"));
+        action.args(&["-c", "kill -9 $$"]);
+        let err = action
+            .build()
+            .run(&root, &root.join("0.log"), &policy)
+            .unwrap_err();
+        assert_eq!(
+            "Error running \"sh\", which was limited to 10s of CPU time",
+            err.to_string()
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "needs unprivileged user namespaces, run it with --ignored"]
+    fn isolated_actions_only_see_their_sandbox_and_toolchains() {
+        let namespaces_available = Command::new("unshare")
+            .args(["-Urn", "true"])
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        assert!(
+            namespaces_available,
+            "Unprivileged user namespaces are not available, so actions can't be isolated"
+        );
+
+        let dir = std::env::temp_dir().join("zap-core-action-jail");
+        let _ = std::fs::remove_dir_all(&dir);
+        let root = dir.join("sandbox");
+        let toolchain = dir.join("toolchain");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&toolchain).unwrap();
+        std::fs::write(toolchain.join("tool"), "tool").unwrap();
+        std::fs::write(dir.join("secret"), "hunter2").unwrap();
+
+        let policy = ExecPolicy {
+            isolation: Isolation::Namespaces,
+            read_only_paths: vec![toolchain.clone()],
+            ..ExecPolicy::default()
+        };
+        let mut action = Action::exec(PathBuf::from("sh"));
+        action.args(&[
+            "-c",
+            "cat ../toolchain/tool; cat ../secret; echo ok > out; echo no > ../toolchain/tool; ls /proc /dev > devices; echo ok > /dev/null",
+        ]);
+        let _ = action.build().run(&root, &root.join("0.log"), &policy);
+
+        assert_eq!("ok\n", std::fs::read_to_string(root.join("out")).unwrap());
+        assert_eq!(
+            "tool",
+            std::fs::read_to_string(toolchain.join("tool")).unwrap()
+        );
+        let log = std::fs::read_to_string(root.join("0.log")).unwrap();
+        assert!(log.starts_with("tool"), "{}", log);
+        assert!(!log.contains("hunter2"), "{}", log);
+
+        let devices = std::fs::read_to_string(root.join("devices")).unwrap();
+        for device in devices.lines().filter(|line| !line.is_empty()) {
+            assert!(
+                ["/dev:", "null", "tty", "urandom", "zero"].contains(&device),
+                "{}",
+                devices
+            );
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn strict_actions_fail_when_reading_undeclared_files() {
+        let workspace = std::env::temp_dir().join("zap-core-action-strict");
+        let _ = std::fs::remove_dir_all(&workspace);
+        let root = workspace.join(".zap/sandbox/hash");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(workspace.join("declared.h"), "").unwrap();
+        std::os::unix::fs::symlink(workspace.join("declared.h"), root.join("declared.h")).unwrap();
+        std::fs::write(workspace.join("undeclared.h"), "").unwrap();
+
+        let policy = ExecPolicy {
+            strict: true,
+            workspace_root: Some(workspace.clone()),
+            ..ExecPolicy::default()
+        };
+        let mut action = Action::exec(PathBuf::from("cat"));
+        action.args(&["declared.h"]);
+        action
+            .build()
+            .run(&root, &root.join("0.log"), &policy)
+            .unwrap();
+
+        let mut action = Action::exec(PathBuf::from("cat"));
+        action.args(&["declared.h", "../../../undeclared.h"]);
+        let err = action
+            .build()
+            .run(&root, &root.join("0.log"), &policy)
+            .unwrap_err();
+        assert_eq!(
+            "\"cat\" read files that are not srcs of its target or outputs of its dependencies:\n\n    undeclared.h\n\nDeclare them as srcs, so the target is rebuilt when they change.",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn distinguishes_declared_environments() {
+        let a = Action::exec(PathBuf::from("erlc"));
+        let mut b = Action::exec(PathBuf::from("erlc"));
+        b.env("ERL_LIBS", "a");
+        let mut c = Action::exec(PathBuf::from("erlc"));
+        c.env("ERL_LIBS", "b");
+        assert_ne!(hash(&a.build()), hash(&b.clone().build()));
+        assert_ne!(hash(&b.build()), hash(&c.build()));
+    }
+
+    #[test]
+    fn only_inherits_the_allowed_variables() {
+        let parent: BTreeMap<String, String> = vec![
+            ("INHERITED".to_string(), "inherited".to_string()),
+            ("LEAKED".to_string(), "leaked".to_string()),
+            ("DECLARED".to_string(), "parent".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let env: BTreeMap<String, String> = vec![("DECLARED".to_string(), "declared".to_string())]
+            .into_iter()
+            .collect();
+        let inherit_env = vec![
+            "INHERITED".to_string(),
+            "DECLARED".to_string(),
+            "UNSET".to_string(),
+        ];
+
+        let environment =
+            ExecAction::environment(&env, &inherit_env, |key| parent.get(key).cloned());
+
+        assert_eq!(Some(&"inherited".to_string()), environment.get("INHERITED"));
+        assert_eq!(Some(&"declared".to_string()), environment.get("DECLARED"));
+        assert_eq!(None, environment.get("LEAKED"));
+        assert_eq!(None, environment.get("UNSET"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_exec_actions_with_a_clean_environment() {
+        let root = std::env::temp_dir().join("zap-core-action-env");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+
+        // NOTE: cargo sets CARGO_PKG_NAME for the tests, so it would leak into
+        // the action if the environment was not cleared.
+        let mut action = Action::exec(PathBuf::from("sh"));
+        action
+            .args(&["-c", "echo $CARGO_PKG_NAME $DECLARED"])
+            .env("DECLARED", "declared");
+        action
+            .build()
+            .run(&root, &root.join("0.log"), &ExecPolicy::default())
+            .unwrap();
+
+        assert_eq!(
+            "declared\n",
+            std::fs::read_to_string(root.join("0.log")).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn distinguishes_paths_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let a = Action::copy(
+            PathBuf::from(OsStr::from_bytes(b"a/\xff")),
+            PathBuf::from("b"),
+        );
+        let b = Action::copy(
+            PathBuf::from(OsStr::from_bytes(b"a/\xfe")),
+            PathBuf::from("b"),
+        );
+        assert_ne!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn timeouts_are_not_part_of_the_encoding() {
+        let a = Action::exec(PathBuf::from("erlc"));
+        let mut b = Action::exec(PathBuf::from("erlc"));
+        b.timeout(Duration::from_secs(1));
+        assert_eq!(hash(&a.build()), hash(&b.build()));
+    }
+
+    #[test]
+    fn distinguishes_missing_and_empty_cwd() {
+        let a = Action::exec(PathBuf::from("erlc"));
+        let mut b = Action::exec(PathBuf::from("erlc"));
+        b.cwd(&PathBuf::from(""));
+        assert_ne!(hash(&a.build()), hash(&b.build()));
+    }
+}
@@ -0,0 +1,216 @@
+use super::Label;
+use anyhow::Context;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// ActionLogs keep the output of every action run to build a target, so
+/// warnings are not lost when a build succeeds.
+///
+/// Logs live in `<logs root>/<target hash>/<n>.log`, where `n` is the
+/// position of the action within the target, and hold both the stdout and
+/// the stderr of the action, in the order they were written.
+///
+/// The label of the target is kept next to them in a `label` file, so the
+/// last logs of a target can be found even after its hash has changed.
+///
+/// Logs are local to the workspace that ran the actions: they are not part of
+/// the remote cache, so targets fetched from it have no logs.
+///
+#[derive(Debug, Clone)]
+pub struct ActionLogs {
+    dir: PathBuf,
+}
+
+impl ActionLogs {
+    pub fn new(logs_root: &Path, hash: &str) -> ActionLogs {
+        ActionLogs {
+            dir: logs_root.join(hash),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The log file for the `n`th action of the target.
+    pub fn path(&self, n: usize) -> PathBuf {
+        self.dir.join(format!("{}.log", n))
+    }
+
+    /// Remove the logs of any previous run, so they are not mixed with the
+    /// logs of the next one.
+    pub fn reset(&self, label: &Label) -> Result<(), anyhow::Error> {
+        let _ = std::fs::remove_dir_all(&self.dir);
+        std::fs::create_dir_all(&self.dir).context(format!(
+            "Could not create logs directory at {:?}",
+            &self.dir
+        ))?;
+        std::fs::write(self.dir.join("label"), label.to_string())
+            .context(format!("Could not label logs directory at {:?}", &self.dir))
+    }
+
+    pub fn label(&self) -> Option<Label> {
+        std::fs::read_to_string(self.dir.join("label"))
+            .ok()
+            .map(|label| label.trim().into())
+    }
+
+    /// Read the logs of every action, in the order the actions ran.
+    pub fn read(&self) -> Result<Vec<(usize, String)>, anyhow::Error> {
+        let mut logs = vec![];
+        if !self.dir.is_dir() {
+            return Ok(logs);
+        }
+
+        for entry in std::fs::read_dir(&self.dir)
+            .context(format!("Could not read logs directory at {:?}", &self.dir))?
+        {
+            let path = entry?.path();
+            let n = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("log") => path.file_stem().and_then(|n| n.to_str()?.parse().ok()),
+                _ => None,
+            };
+            if let Some(n) = n {
+                let log =
+                    std::fs::read(&path).context(format!("Could not read log at {:?}", &path))?;
+                logs.push((n, String::from_utf8_lossy(&log).to_string()));
+            }
+        }
+        logs.sort_by_key(|(n, _)| *n);
+
+        Ok(logs)
+    }
+
+    /// Find the most recent logs of a target.
+    pub fn latest(logs_root: &Path, label: &Label) -> Result<Option<ActionLogs>, anyhow::Error> {
+        if !logs_root.is_dir() {
+            return Ok(None);
+        }
+
+        let mut latest = None;
+        for entry in std::fs::read_dir(logs_root)
+            .context(format!("Could not read logs directory at {:?}", logs_root))?
+        {
+            let entry = entry?;
+            let logs = ActionLogs { dir: entry.path() };
+            if logs.label().as_ref() != Some(label) {
+                continue;
+            }
+            let modified = entry.metadata()?.modified()?;
+            match &latest {
+                Some((latest_modified, _)) if *latest_modified >= modified => (),
+                _ => latest = Some((modified, logs)),
+            }
+        }
+
+        Ok(latest.map(|(_, logs)| logs))
+    }
+
+    /// Remove the logs of every target whose hash is not in `keep`, returning
+    /// how many were (or would be, on a dry run) removed.
+    pub fn prune(
+        logs_root: &Path,
+        keep: &HashSet<String>,
+        dry_run: bool,
+    ) -> Result<usize, anyhow::Error> {
+        if !logs_root.is_dir() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        for entry in std::fs::read_dir(logs_root)
+            .context(format!("Could not read logs directory at {:?}", logs_root))?
+        {
+            let path = entry?.path();
+            let hash = path.file_name().and_then(|name| name.to_str());
+            if !path.is_dir() || hash.map(|hash| keep.contains(hash)).unwrap_or(false) {
+                continue;
+            }
+            if !dry_run {
+                std::fs::remove_dir_all(&path)
+                    .context(format!("Could not remove logs at {:?}", &path))?;
+            }
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logs_root(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("zap-core-action-logs").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn reads_logs_in_action_order() {
+        let root = logs_root("order");
+        let logs = ActionLogs::new(&root, "hash");
+        logs.reset(&Label::new("a")).unwrap();
+        std::fs::write(logs.path(10), "third").unwrap();
+        std::fs::write(logs.path(2), "second").unwrap();
+        std::fs::write(logs.path(0), "first").unwrap();
+
+        assert_eq!(
+            vec![
+                (0, "first".to_string()),
+                (2, "second".to_string()),
+                (10, "third".to_string())
+            ],
+            logs.read().unwrap()
+        );
+
+        logs.reset(&Label::new("a")).unwrap();
+        assert!(logs.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_the_latest_logs_of_a_target() {
+        let root = logs_root("latest");
+        assert!(ActionLogs::latest(&root, &Label::new("a"))
+            .unwrap()
+            .is_none());
+
+        let old = ActionLogs::new(&root, "old");
+        old.reset(&Label::new("a")).unwrap();
+        let other = ActionLogs::new(&root, "other");
+        other.reset(&Label::new("b")).unwrap();
+        let new = ActionLogs::new(&root, "new");
+        new.reset(&Label::new("a")).unwrap();
+
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::open(old.dir())
+            .unwrap()
+            .set_modified(an_hour_ago)
+            .unwrap();
+
+        let latest = ActionLogs::latest(&root, &Label::new("a"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(new.dir(), latest.dir());
+    }
+
+    #[test]
+    fn prunes_the_logs_of_targets_that_are_not_kept() {
+        let root = logs_root("prune");
+        ActionLogs::new(&root, "live")
+            .reset(&Label::new("a"))
+            .unwrap();
+        ActionLogs::new(&root, "stale")
+            .reset(&Label::new("a"))
+            .unwrap();
+        let keep: HashSet<String> = vec!["live".to_string()].into_iter().collect();
+
+        assert_eq!(1, ActionLogs::prune(&root, &keep, true).unwrap());
+        assert!(root.join("stale").is_dir());
+
+        assert_eq!(1, ActionLogs::prune(&root, &keep, false).unwrap());
+        assert!(root.join("live").is_dir());
+        assert!(!root.join("stale").exists());
+    }
+}
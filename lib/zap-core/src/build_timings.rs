@@ -0,0 +1,108 @@
+use super::Label;
+use anyhow::{anyhow, Context};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// BuildTimings keep track of how long the last build of every target took.
+///
+/// They are kept in the `.zap` folder of the workspace as plain text, with one
+/// `<milliseconds>  <label>` line per target, sorted by label.
+///
+/// Targets that were fetched from the cache keep the duration of their last
+/// actual build.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildTimings {
+    durations: HashMap<String, Duration>,
+}
+
+impl BuildTimings {
+    pub fn new() -> BuildTimings {
+        BuildTimings::default()
+    }
+
+    pub fn record(&mut self, label: &Label, duration: Duration) {
+        self.durations.insert(label.to_string(), duration);
+    }
+
+    pub fn get(&self, label: &Label) -> Option<Duration> {
+        self.durations.get(&label.to_string()).cloned()
+    }
+
+    pub fn parse(contents: &str) -> Result<BuildTimings, anyhow::Error> {
+        let mut timings = BuildTimings::new();
+        for (n, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, "  ");
+            match (parts.next().map(|ms| ms.parse::<u64>()), parts.next()) {
+                (Some(Ok(ms)), Some(label)) if !label.is_empty() => {
+                    timings
+                        .durations
+                        .insert(label.to_string(), Duration::from_millis(ms));
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Malformed build timing on line {}: {:?}",
+                        n + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(timings)
+    }
+
+    /// Read the timings at a given path. If there are none yet, no target
+    /// has a known duration.
+    pub fn read(path: &Path) -> Result<BuildTimings, anyhow::Error> {
+        if !path.exists() {
+            return Ok(BuildTimings::new());
+        }
+        let contents = std::fs::read_to_string(path)
+            .context(format!("Could not read build timings at {:?}", path))?;
+        BuildTimings::parse(&contents).context(format!("Invalid build timings at {:?}", path))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        std::fs::write(path, self.to_string())
+            .context(format!("Could not write build timings at {:?}", path))
+    }
+}
+
+impl std::fmt::Display for BuildTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut durations: Vec<(&String, &Duration)> = self.durations.iter().collect();
+        durations.sort();
+        for (label, duration) in durations {
+            writeln!(f, "{}  {}", duration.as_millis(), label)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timings(durations: &[(&str, u64)]) -> BuildTimings {
+        let mut timings = BuildTimings::new();
+        for (name, secs) in durations {
+            timings.record(&Label::new(name), Duration::from_secs(*secs));
+        }
+        timings
+    }
+
+    #[test]
+    fn timings_roundtrip_through_text() {
+        let timings = timings(&[("b", 2), ("a", 1)]);
+        assert_eq!("1000  :a\n2000  :b\n", timings.to_string());
+        assert_eq!(timings, BuildTimings::parse(&timings.to_string()).unwrap());
+        assert_eq!(
+            "Malformed build timing on line 1: \"1s  :a\"",
+            BuildTimings::parse("1s  :a").unwrap_err().to_string()
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use super::*;
 use anyhow::*;
 use log::*;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::vec::Vec;
 use toml::Value;
 
@@ -49,6 +50,7 @@ impl Buildfile {
             .to_path_buf();
 
         let pkg_prefix = package_dir.strip_prefix(workspace_prefix)?.to_path_buf();
+        let pkg_name = Buildfile::package_name(&pkg_prefix)?;
 
         let mut targets: Vec<Target> = vec![];
 
@@ -65,7 +67,11 @@ impl Buildfile {
                     ))?
                 };
 
-                let label = Label::from_path_and_name(&pkg_prefix, name);
+                let label = Label::parse(&format!("//{}:{}", pkg_name, name))
+                    .context(format!(
+                        "Rule {} in file {:?} has an invalid name",
+                        &rule_name, &zapfile_path
+                    ))?;
                 let rule = rule_manager.get(rule_name).context(format!("Could not find a rule named `{}`, are you sure its spelled correctly and installed in   {}/.zap/rules  ?  \n\nAvailable rules are: {:?}", rule_name, workspace_prefix.to_str().unwrap(), rule_manager.rules()))?;
 
                 let rule_config = {
@@ -74,13 +80,18 @@ impl Buildfile {
                             cfg
                     ))?;
 
+                    Buildfile::check_attributes(table, &rule, &label, zapfile_path)?;
+
                     // NOTE(@ostera): DashMap deadlocks if you take a read and a write borrow on
                     // the same key!
                     let values: RuleConfig = rule.defaults().clone();
 
                     for (key, value_type) in rule.config().as_map().iter() {
                         let value = match table.get(key) {
-                            Some(value) => Buildfile::parse_config_value(value, value_type)?,
+                            Some(value) => Buildfile::parse_config_value(value, value_type).context(format!(
+                                "Invalid attribute {:?} of {} in file {:?}",
+                                key, label.to_string(), &zapfile_path
+                            ))?,
                             None => values.get(key).context(format!("When building   {}  I did not find the attribute {:?} on the Build.toml, which is mandatory. You can add it like this:
 
 {} = <value>
@@ -109,6 +120,62 @@ impl Buildfile {
         })
     }
 
+    /// The package part of the labels of the targets in a Buildfile, with its
+    /// path components joined by `/` regardless of the platform.
+    fn package_name(pkg_prefix: &Path) -> Result<String, anyhow::Error> {
+        let components = pkg_prefix
+            .components()
+            .map(|component| {
+                component.as_os_str().to_str().context(format!(
+                    "Package path {:?} is not valid UTF-8, so its targets can't be labeled",
+                    pkg_prefix
+                ))
+            })
+            .collect::<Result<Vec<&str>, anyhow::Error>>()?;
+        Ok(components.join("/"))
+    }
+
+    /// Reject attributes that the rule does not define, so a typo like `src`
+    /// doesn't silently fall back to the default value of `srcs`.
+    fn check_attributes(
+        table: &toml::value::Table,
+        rule: &Rule,
+        label: &Label,
+        zapfile_path: &PathBuf,
+    ) -> Result<(), anyhow::Error> {
+        let mut valid = rule.config().keys();
+        if !valid.iter().any(|key| key == "name") {
+            valid.push("name".to_string());
+        }
+        valid.sort();
+
+        for key in table.keys() {
+            if valid.contains(key) {
+                continue;
+            }
+
+            let suggestion = valid
+                .iter()
+                .map(|attr| (edit_distance(key, attr), attr))
+                .filter(|(distance, _)| *distance <= std::cmp::max(1, key.len() / 3))
+                .min_by_key(|(distance, _)| *distance)
+                .map(|(_, attr)| format!(" Did you mean {:?}?", attr))
+                .unwrap_or_default();
+
+            return Err(anyhow!(
+                "Unknown attribute {:?} on   {}  in file {:?}.{}\n\nValid attributes for {} are: {}",
+                key,
+                label.to_string(),
+                zapfile_path,
+                suggestion,
+                rule.name(),
+                valid.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
     pub fn targets(self) -> Vec<Target> {
         self.targets
     }
@@ -128,8 +195,7 @@ impl Buildfile {
                 let s = value
                     .as_str()
                     .context(format!("Expected Label but found: {:?}", value))?;
-                let l = Label::new(s);
-                Ok(CfgValue::Label(l))
+                Ok(CfgValue::Label(Label::parse(s)?))
             }
             CfgValueType::File => {
                 let s = value
@@ -137,6 +203,18 @@ impl Buildfile {
                     .context(format!("Expected File path but found: {:?}", value))?;
                 Ok(CfgValue::File(PathBuf::from(s)))
             }
+            CfgValueType::Bool => {
+                let b = value
+                    .as_bool()
+                    .context(format!("Expected Boolean but found: {:?}", value))?;
+                Ok(CfgValue::Bool(b))
+            }
+            CfgValueType::Int => {
+                let i = value
+                    .as_integer()
+                    .context(format!("Expected Integer but found: {:?}", value))?;
+                Ok(CfgValue::Int(i))
+            }
             CfgValueType::List(inner) => {
                 let arr = value
                     .as_array()
@@ -153,9 +231,38 @@ impl Buildfile {
 
                 Ok(CfgValue::List(elements))
             }
+            CfgValueType::StringDict => {
+                let mut entries = HashMap::new();
+                for (k, v) in Buildfile::parse_dict(value)? {
+                    entries.insert(k.to_string(), v);
+                }
+                Ok(CfgValue::StringDict(entries))
+            }
+            CfgValueType::LabelKeyedDict => {
+                let mut entries = HashMap::new();
+                for (k, v) in Buildfile::parse_dict(value)? {
+                    entries.insert(Label::parse(k)?, v);
+                }
+                Ok(CfgValue::LabelKeyedDict(entries))
+            }
         }
     }
 
+    fn parse_dict(value: &toml::Value) -> Result<Vec<(&str, String)>, anyhow::Error> {
+        let table = value
+            .as_table()
+            .context(format!("Expected Table but found: {:?}", value))?;
+        let mut entries = vec![];
+        for (k, v) in table {
+            let v = v.as_str().context(format!(
+                "Expected the value of {:?} to be a String but found: {:?}",
+                k, v
+            ))?;
+            entries.push((k.as_str(), v.to_string()));
+        }
+        Ok(entries)
+    }
+
     pub fn expand_value(
         value: CfgValue,
         zapfile_path: &PathBuf,
@@ -198,3 +305,137 @@ impl Buildfile {
         }
     }
 }
+
+/// The number of single character insertions, deletions and substitutions
+/// needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &str, cfg_type: CfgValueType) -> Result<CfgValue, anyhow::Error> {
+        let value = format!("value = {}", value).parse::<Value>().unwrap();
+        Buildfile::parse_config_value(&value["value"], &cfg_type)
+    }
+
+    #[test]
+    fn parses_scalar_values() {
+        assert!(matches!(
+            parse("true", CfgValueType::Bool).unwrap(),
+            CfgValue::Bool(true)
+        ));
+        assert!(matches!(
+            parse("2", CfgValueType::Int).unwrap(),
+            CfgValue::Int(2)
+        ));
+        assert!(parse("\"true\"", CfgValueType::Bool).is_err());
+        assert!(parse("2.5", CfgValueType::Int).is_err());
+    }
+
+    #[test]
+    fn parses_dict_values() {
+        match parse("{ d = \"TEST\" }", CfgValueType::StringDict).unwrap() {
+            CfgValue::StringDict(entries) => {
+                assert_eq!(Some(&"TEST".to_string()), entries.get("d"))
+            }
+            value => panic!("Expected a StringDict but found {:?}", value),
+        }
+
+        match parse("{ \"//a:b\" = \"b.hrl\" }", CfgValueType::LabelKeyedDict).unwrap() {
+            CfgValue::LabelKeyedDict(entries) => {
+                assert_eq!(
+                    Some(&"b.hrl".to_string()),
+                    entries.get(&Label::new("//a:b"))
+                )
+            }
+            value => panic!("Expected a LabelKeyedDict but found {:?}", value),
+        }
+
+        assert!(parse("{ d = 1 }", CfgValueType::StringDict).is_err());
+        assert!(parse("{ \"//a\" = \"a\" }", CfgValueType::LabelKeyedDict).is_err());
+    }
+
+    #[test]
+    fn names_packages_after_their_path() {
+        assert_eq!("", Buildfile::package_name(Path::new("")).unwrap());
+        assert_eq!(
+            "a/b/c",
+            Buildfile::package_name(&Path::new("a").join("b").join("c")).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_packages_that_are_not_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        assert!(Buildfile::package_name(Path::new(OsStr::from_bytes(b"a/\xff"))).is_err());
+    }
+
+    fn check(attributes: &str) -> Result<(), anyhow::Error> {
+        let mut cfg = std::collections::HashMap::new();
+        cfg.insert(
+            "srcs".to_string(),
+            CfgValueType::List(Box::new(CfgValueType::File)),
+        );
+        cfg.insert(
+            "deps".to_string(),
+            CfgValueType::List(Box::new(CfgValueType::Label)),
+        );
+        let rule = Rule::new(
+            "erlang_library".to_string(),
+            "ErlangLibrary".to_string(),
+            vec![],
+            ConfigSpec(cfg),
+            RuleConfig::default(),
+        );
+        let table = attributes.parse::<Value>().unwrap();
+        Buildfile::check_attributes(
+            table.as_table().unwrap(),
+            &rule,
+            &Label::new("//a:b"),
+            &PathBuf::from("a/Build.toml"),
+        )
+    }
+
+    #[test]
+    fn accepts_known_attributes() {
+        assert!(check("name = \"b\"\nsrcs = []\ndeps = []").is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_attributes() {
+        let err = check("name = \"b\"\nsrc = []").unwrap_err().to_string();
+        assert!(err.contains("\"src\""));
+        assert!(err.contains("//a:b"));
+        assert!(err.contains("a/Build.toml"));
+        assert!(err.contains("Did you mean \"srcs\"?"));
+        assert!(err.contains("deps, name, srcs"));
+
+        let err = check("optimize = true").unwrap_err().to_string();
+        assert!(!err.contains("Did you mean"));
+    }
+
+    #[test]
+    fn computes_edit_distance() {
+        assert_eq!(0, edit_distance("srcs", "srcs"));
+        assert_eq!(1, edit_distance("src", "srcs"));
+        assert_eq!(1, edit_distance("deps", "dops"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        assert_eq!(4, edit_distance("", "deps"));
+    }
+}
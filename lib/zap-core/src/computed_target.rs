@@ -1,11 +1,12 @@
-use super::{Action, Label, Target};
+use super::{Action, ActionLogs, ExecPolicy, Label, Profiler, Target};
 use anyhow::*;
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
 use dashmap::DashMap;
 use log::*;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use zap_buildscript::*;
 
 #[derive(Clone, Debug)]
@@ -13,6 +14,9 @@ pub struct Dependency {
     pub label: Label,
     pub hash: String,
     pub outs: Vec<PathBuf>,
+
+    /// Where the archive of this dependency is unpacked, if it has one.
+    pub root: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -38,13 +42,16 @@ pub struct ComputedTarget {
     status: ComputeStatus,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ComputeStatus {
     Uninitialized,
     Pending,
     CacheHit,
     Succeeded,
     Failed,
+
+    /// Not built because one of its dependencies failed.
+    Skipped,
 }
 
 impl Default for ComputeStatus {
@@ -71,6 +78,10 @@ impl ComputedTarget {
             label: self.target.label().clone(),
             hash: self.hash(),
             outs: self.outs(),
+            root: self
+                .target
+                .archive()
+                .map(|archive| archive.unarchived_root()),
         }
     }
 
@@ -82,6 +93,18 @@ impl ComputedTarget {
         self.status = ComputeStatus::Succeeded;
     }
 
+    pub fn mark_cache_hit(&mut self) {
+        self.status = ComputeStatus::CacheHit;
+    }
+
+    pub fn mark_skipped(&mut self) {
+        self.status = ComputeStatus::Skipped;
+    }
+
+    pub fn status(&self) -> &ComputeStatus {
+        &self.status
+    }
+
     pub fn hash(&self) -> String {
         if self.target.is_local() {
             self.hash.clone().unwrap_or_else(|| {
@@ -137,8 +160,12 @@ impl ComputedTarget {
 
     pub fn execute(
         &self,
+        working_dir: &Path,
         archive_root: &PathBuf,
         cache_root: &PathBuf,
+        profiler: &Profiler,
+        logs: &ActionLogs,
+        policy: &ExecPolicy,
     ) -> Result<(), anyhow::Error> {
         if let Some(archive) = &self.target.archive() {
             trace!("Target has an archive, preparing...");
@@ -158,8 +185,13 @@ impl ComputedTarget {
             }
         }
 
-        for action in self.actions() {
-            action.run()?
+        logs.reset(self.label())?;
+        let label = self.target.label().to_string();
+        for (n, action) in self.actions().into_iter().enumerate() {
+            let _span = profiler.target_span("action", &format!("action #{}", n), &label);
+            action
+                .run(working_dir, &logs.path(n), policy)
+                .context(format!("Action #{} of {} failed", n, label))?
         }
 
         Ok(())
@@ -239,7 +271,7 @@ impl ComputedTarget {
         self.outs = Some(outs);
         self.actions = Some(actions);
 
-        self.update_hash();
+        self.update_hash()?;
 
         debug!(
             "Sealed ComputedTarget {} with Hash {:?}",
@@ -257,7 +289,7 @@ impl ComputedTarget {
     /// * rule name
     /// * the hash of the computed actions that this target will execute
     ///
-    fn update_hash(&mut self) {
+    fn update_hash(&mut self) -> Result<(), anyhow::Error> {
         let mut hasher = Sha1::new();
 
         let name = self.target.label();
@@ -268,9 +300,11 @@ impl ComputedTarget {
         }
 
         for src_path in self.srcs.as_ref().unwrap() {
-            let contents = fs::read_to_string(&src_path)
-                .unwrap_or_else(|_| panic!("Truly expected {:?} to be a readable file. Was it changed since the build started?", src_path));
-            hasher.input_str(&contents);
+            ComputedTarget::hash_file(&mut hasher, src_path).context(format!(
+                "Could not hash source file {:?} of target {}. Was it changed since the build started?",
+                src_path,
+                name.to_string()
+            ))?;
         }
 
         for o in self.outs.as_ref().unwrap() {
@@ -278,12 +312,35 @@ impl ComputedTarget {
         }
 
         for a in self.actions.as_ref().unwrap() {
-            // TODO(@ostera): implement Hash for Action
-            hasher.input_str(&format!("{:?}", a));
+            hasher.input(&a.encode());
         }
 
         let hash = hasher.result_str();
         self.hash = Some(hash);
+
+        Ok(())
+    }
+
+    /// Feed the raw contents of a file into the hasher, without reading the
+    /// entire file into memory.
+    fn hash_file(hasher: &mut Sha1, path: &Path) -> Result<(), io::Error> {
+        let mut file = fs::File::open(path)?;
+        io::copy(&mut file, &mut HashWriter(hasher))?;
+        Ok(())
+    }
+}
+
+/// Adapter to stream any `Read` into a `Digest` using `std::io::copy`.
+struct HashWriter<'a, D: Digest>(&'a mut D);
+
+impl<'a, D: Digest> io::Write for HashWriter<'a, D> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.input(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
@@ -388,6 +445,7 @@ mod tests {
                 label: Label::new(":a"),
                 hash: "some-hash".to_string(),
                 outs: vec![],
+                root: None,
             }],
             &toolchain_mgr(),
         )
@@ -397,3 +455,66 @@ mod tests {
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConfigSpec, Rule, RuleConfig};
+
+    fn target_with_srcs(srcs: Vec<PathBuf>) -> ComputedTarget {
+        let rule = Rule::new(
+            "test_rule".to_string(),
+            "TestRule".to_string(),
+            vec![],
+            ConfigSpec::default(),
+            RuleConfig::default(),
+        );
+        let target = Target::local(Label::new("//test:target"), &rule, RuleConfig::default());
+        let mut computed_target = ComputedTarget::from_target(target);
+        computed_target.deps = Some(vec![]);
+        computed_target.srcs = Some(srcs);
+        computed_target.outs = Some(vec![]);
+        computed_target.actions = Some(vec![]);
+        computed_target
+    }
+
+    fn write_fixture(name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join("zap-core-computed-target-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn hashes_binary_sources() {
+        let src = write_fixture("binary.beam", &[0xff, 0xfe, 0x00, 0xc3, 0x28]);
+        let mut target = target_with_srcs(vec![src]);
+        target.update_hash().unwrap();
+        assert_eq!(40, target.hash().len());
+    }
+
+    #[test]
+    fn source_contents_are_part_of_hashing() {
+        let a = write_fixture("a.erl", b"-module(a).");
+        let b = write_fixture("b.erl", b"-module(b).");
+
+        let mut target_a = target_with_srcs(vec![a]);
+        target_a.update_hash().unwrap();
+        let mut target_b = target_with_srcs(vec![b]);
+        target_b.update_hash().unwrap();
+
+        assert_ne!(target_a.hash(), target_b.hash());
+    }
+
+    #[test]
+    fn fails_to_hash_missing_sources() {
+        let src = PathBuf::from("this/file/does/not/exist.erl");
+        let mut target = target_with_srcs(vec![src]);
+        let err = target.update_hash().unwrap_err();
+        assert_eq!(
+            r#"Could not hash source file "this/file/does/not/exist.erl" of target //test:target. Was it changed since the build started?"#,
+            err.to_string()
+        );
+    }
+}
@@ -1,7 +1,48 @@
+use super::Profiler;
 use anyhow::*;
 use directories::ProjectDirs;
 use std::path::PathBuf;
 
+/// How files are put in place when moving them between the workspace, the
+/// sandboxes, and the cache.
+///
+/// Every strategy other than `Copy` falls back to copying when it is not
+/// supported, for example across filesystems.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MaterializationStrategy {
+    #[default]
+    Copy,
+
+    /// Hardlinked files share their contents, so modifying one (for example,
+    /// an output in `zap-outputs`) modifies the cached one as well. Cache
+    /// entries are verified before being used, so this will only cause a
+    /// rebuild.
+    Hardlink,
+
+    /// Symlinked files point to the original file, and break if it is removed.
+    Symlink,
+
+    /// Copy-on-write copies, on filesystems that support them (Btrfs, XFS).
+    Reflink,
+}
+
+impl std::str::FromStr for MaterializationStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(strategy: &str) -> Result<MaterializationStrategy, anyhow::Error> {
+        match strategy {
+            "copy" => Ok(MaterializationStrategy::Copy),
+            "hardlink" => Ok(MaterializationStrategy::Hardlink),
+            "symlink" => Ok(MaterializationStrategy::Symlink),
+            "reflink" => Ok(MaterializationStrategy::Reflink),
+            _ => Err(anyhow!(
+                "Unknown materialization strategy {:?}, expected one of: copy, hardlink, symlink, reflink",
+                strategy
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ZapConfig {
     project_dirs: ProjectDirs,
@@ -20,6 +61,22 @@ pub struct ZapConfig {
 
     /// The user running this command.
     pub user: String,
+
+    /// The URL of a remote cache to fetch build outputs from.
+    pub remote_cache: Option<String>,
+
+    /// Whether build outputs should be uploaded to the remote cache.
+    pub remote_cache_upload: bool,
+
+    /// The maximum size of the cache in bytes. Least recently used entries
+    /// will be evicted after every build to stay under it.
+    pub max_cache_size: Option<u64>,
+
+    /// How files are put in place in sandboxes, outputs, and the cache.
+    pub materialization: MaterializationStrategy,
+
+    /// Records how long every phase of a build takes.
+    pub profiler: Profiler,
 }
 
 impl ZapConfig {
@@ -63,6 +120,79 @@ impl ZapConfig {
             rules_root,
             toolchains_root,
             user,
+            remote_cache: None,
+            remote_cache_upload: false,
+            max_cache_size: None,
+            materialization: MaterializationStrategy::default(),
+            profiler: Profiler::default(),
         })
     }
+
+    pub fn with_remote_cache(self, remote_cache: Option<String>, upload: bool) -> ZapConfig {
+        ZapConfig {
+            remote_cache,
+            remote_cache_upload: upload,
+            ..self
+        }
+    }
+
+    pub fn with_materialization(self, materialization: MaterializationStrategy) -> ZapConfig {
+        ZapConfig {
+            materialization,
+            ..self
+        }
+    }
+
+    pub fn with_profiler(self, profiler: Profiler) -> ZapConfig {
+        ZapConfig { profiler, ..self }
+    }
+
+    pub fn with_max_cache_size(self, max_cache_size: Option<u64>) -> ZapConfig {
+        ZapConfig {
+            max_cache_size,
+            ..self
+        }
+    }
+}
+
+/// Parse a size in bytes, optionally followed by a K, M, G, or T suffix, like
+/// `512M` or `10G`.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim().to_uppercase();
+    let size = size.trim_end_matches('B');
+    let (number, multiplier) = match size.chars().last() {
+        Some('K') => (&size[..size.len() - 1], 1 << 10),
+        Some('M') => (&size[..size.len() - 1], 1 << 20),
+        Some('G') => (&size[..size.len() - 1], 1 << 30),
+        Some('T') => (&size[..size.len() - 1], 1 << 40),
+        _ => (size, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .map_err(|_| format!("Invalid size: {:?}", size))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Size {:?} is too large", size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(Ok(512), parse_size("512"));
+        assert_eq!(Ok(2048), parse_size("2K"));
+        assert_eq!(Ok(4 << 30), parse_size("4gb"));
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        assert_eq!(
+            Err("Size \"99999999999G\" is too large".to_string()),
+            parse_size("99999999999G")
+        );
+        assert!(parse_size("99999999999999999999G").is_err());
+    }
 }
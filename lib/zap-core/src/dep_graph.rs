@@ -1,4 +1,4 @@
-use super::{Action, ComputedTarget, Dependency, Label, Target};
+use super::{Action, BuildTimings, ComputedTarget, Dependency, Label, Target, TargetPattern};
 use anyhow::{anyhow, Context};
 use daggy::{Dag, NodeIndex};
 use dashmap::DashMap;
@@ -7,6 +7,7 @@ use petgraph::dot;
 use petgraph::{stable_graph::StableDiGraph, Direction};
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 use zap_buildscript::*;
 
 /// The DepGraph contains the graph of all the targets in this project.
@@ -20,11 +21,11 @@ use zap_buildscript::*;
 /// want to build a subset of the entirety of a project, so scoping the path to
 /// our target node throughout this build graph is incredibly useful.
 ///
-/// To do this, we can call `build_plan.scoped(label)` where Label is our target
-/// node's label.
+/// To do this, we can call `build_plan.scoped(labels)` where the Labels are our
+/// target nodes' labels.
 ///
 /// This will trim down the build graph to the smallest graph that would satisfy
-/// our target's dependencies transitively.
+/// our targets' dependencies transitively.
 ///
 /// In other words, this struct takes care of figuring out what is the smallest
 /// amount of work that needs to be done.
@@ -98,20 +99,51 @@ impl DepGraph {
         })
     }
 
-    pub fn scoped(&mut self, target: &Label) -> Result<&mut DepGraph, anyhow::Error> {
-        if target.is_all() {
-            Ok(self)
-        } else {
+    pub fn scoped(&mut self, targets: &[Label]) -> Result<&mut DepGraph, anyhow::Error> {
+        if targets.iter().any(Label::is_all) {
+            return Ok(self);
+        }
+
+        let mut nodes_to_keep = HashMap::new();
+        for target in targets {
             let node_index = self
                 .nodes
                 .get(&target)
                 .context(format!("Could not find node: {:?}", &target))?;
-            let nodes_to_keep = DepGraph::subgraph(&mut self._inner_graph, *node_index);
-            self._inner_graph
-                .retain_nodes(|_g, node| nodes_to_keep.contains_key(&node));
+            nodes_to_keep.extend(DepGraph::subgraph(&mut self._inner_graph, *node_index));
+        }
+        self._inner_graph
+            .retain_nodes(|_g, node| nodes_to_keep.contains_key(&node));
+
+        Ok(self)
+    }
+
+    /// Find the labels of every target selected by these patterns.
+    pub fn find_matching(&self, patterns: &[TargetPattern]) -> Result<Vec<Label>, anyhow::Error> {
+        // NOTE: only patterns with wildcards may match nothing, so a typo in
+        // one of several targets is not silently left out of the build.
+        let missing: Vec<String> = patterns
+            .iter()
+            .filter_map(|pattern| match pattern {
+                TargetPattern::Target(label) if !self.nodes.contains_key(label) => {
+                    Some(label.to_string())
+                }
+                _ => None,
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!(
+                "Could not find {} in the Build Graph",
+                missing.join(", ")
+            ));
+        }
 
-            Ok(self)
+        let labels = TargetPattern::select(patterns, self.nodes.keys());
+        if labels.is_empty() {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            return Err(anyhow!("No targets match {}", patterns.join(" ")));
         }
+        Ok(labels)
     }
 
     fn subgraph(
@@ -210,6 +242,56 @@ impl DepGraph {
         nodes
     }
 
+    /// Find the chain of dependencies that takes the longest to build, based
+    /// on how long every target took to build the last time.
+    ///
+    /// The path goes from the first target that has to be built to the last
+    /// one, along with how long each of them took. Targets that have never
+    /// been built have no duration, and count as taking no time at all.
+    ///
+    /// No matter how many jobs are used, a build can't be faster than its
+    /// critical path.
+    ///
+    pub fn critical_path(&self, timings: &BuildTimings) -> Vec<(Label, Option<Duration>)> {
+        let graph = &self._inner_graph;
+
+        // The longest path ending at every node, and the dependency it comes
+        // from.
+        let mut longest: HashMap<NodeIndex, (Duration, Option<NodeIndex>)> = HashMap::new();
+        let mut end: Option<(Duration, NodeIndex)> = None;
+
+        let mut walker = petgraph::visit::Topo::new(graph);
+        while let Some(idx) = walker.next(graph) {
+            let mut from = None;
+            let mut elapsed = Duration::from_secs(0);
+            for dep in graph.neighbors_directed(idx, Direction::Incoming) {
+                let (dep_elapsed, _) = longest[&dep];
+                if from.is_none() || dep_elapsed > elapsed {
+                    from = Some(dep);
+                    elapsed = dep_elapsed;
+                }
+            }
+
+            let elapsed = elapsed + timings.get(graph[idx].label()).unwrap_or_default();
+            longest.insert(idx, (elapsed, from));
+
+            match end {
+                Some((longest_elapsed, _)) if longest_elapsed > elapsed => (),
+                _ => end = Some((elapsed, idx)),
+            }
+        }
+
+        let mut path = vec![];
+        let mut next = end.map(|(_, idx)| idx);
+        while let Some(idx) = next {
+            let label = graph[idx].label().clone();
+            path.push((label.clone(), timings.get(&label)));
+            next = longest[&idx].1;
+        }
+        path.reverse();
+        path
+    }
+
     pub fn target_names(&mut self) -> Vec<String> {
         let mut walker = petgraph::visit::Topo::new(&self._inner_graph);
 
@@ -536,3 +618,153 @@ Caused by:
         assert_eq!("[]", format!("{:?}", deps[0].outs));
     }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn target(name: &str, deps: &[&str]) -> Target {
+        let rule = Rule::new(
+            "test_rule".to_string(),
+            "TestRule".to_string(),
+            deps.iter().map(|dep| Label::new(dep)).collect(),
+            ConfigSpec::default(),
+            RuleConfig::default(),
+        );
+        Target::local(Label::new(name), &rule, RuleConfig::default())
+    }
+
+    fn dep_graph() -> DepGraph {
+        //   //a:x <- //b:y
+        //   //a:x <- //c:z
+        //   //d:w
+        DepGraph::from_targets(&[
+            target("//a:x", &[]),
+            target("//b:y", &["//a:x"]),
+            target("//c:z", &["//a:x"]),
+            target("//d:w", &[]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn scopes_to_the_union_of_several_targets() {
+        let mut dep_graph = dep_graph();
+        let mut labels = dep_graph
+            .scoped(&[Label::new("//b:y"), Label::new("//d:w")])
+            .unwrap()
+            .target_names();
+        labels.sort();
+        assert_eq!(vec!["//a:x", "//b:y", "//d:w"], labels);
+    }
+
+    #[test]
+    fn finds_targets_matching_patterns() {
+        let dep_graph = dep_graph();
+        let patterns = vec![
+            TargetPattern::parse("//...").unwrap(),
+            TargetPattern::parse("-//a:all").unwrap(),
+        ];
+        let labels: Vec<String> = dep_graph
+            .find_matching(&patterns)
+            .unwrap()
+            .iter()
+            .map(|label| label.to_string())
+            .collect();
+        assert_eq!(vec!["//b:y", "//c:z", "//d:w"], labels);
+
+        let err = dep_graph
+            .find_matching(&[TargetPattern::parse("//e/...").unwrap()])
+            .unwrap_err();
+        assert_eq!("No targets match //e/...", err.to_string());
+    }
+
+    #[test]
+    fn fails_when_an_explicit_target_is_missing() {
+        let dep_graph = dep_graph();
+        let patterns = vec![
+            TargetPattern::parse("//a:x").unwrap(),
+            TargetPattern::parse("//b:typo").unwrap(),
+            TargetPattern::parse("//e/...").unwrap(),
+        ];
+        let err = dep_graph.find_matching(&patterns).unwrap_err();
+        assert_eq!(
+            "Could not find //b:typo in the Build Graph",
+            err.to_string()
+        );
+
+        let labels = dep_graph
+            .find_matching(&[
+                TargetPattern::parse("//a:x").unwrap(),
+                TargetPattern::parse("//e/...").unwrap(),
+            ])
+            .unwrap();
+        assert_eq!(vec![Label::new("//a:x")], labels);
+    }
+
+    fn timings(durations: &[(&str, u64)]) -> BuildTimings {
+        let mut timings = BuildTimings::new();
+        for (name, secs) in durations {
+            timings.record(&Label::new(name), Duration::from_secs(*secs));
+        }
+        timings
+    }
+
+    #[test]
+    fn finds_the_longest_path_by_duration() {
+        //   a(1) <- b(5) <- d(1)
+        //   a(1) <- c(2) <- d(1)
+        //   e(3) <- d(1)
+        let mut dep_graph = DepGraph::from_targets(&[
+            target("a", &[]),
+            target("b", &["a"]),
+            target("c", &["a"]),
+            target("e", &[]),
+            target("d", &["b", "c", "e"]),
+        ])
+        .unwrap();
+        let timings = timings(&[("a", 1), ("b", 5), ("c", 2), ("d", 1), ("e", 3)]);
+
+        let path: Vec<(String, Option<Duration>)> = dep_graph
+            .scoped(&[Label::new("d")])
+            .unwrap()
+            .critical_path(&timings)
+            .into_iter()
+            .map(|(label, duration)| (label.to_string(), duration))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (":a".to_string(), Some(Duration::from_secs(1))),
+                (":b".to_string(), Some(Duration::from_secs(5))),
+                (":d".to_string(), Some(Duration::from_secs(1))),
+            ],
+            path
+        );
+    }
+
+    #[test]
+    fn targets_never_built_take_no_time() {
+        let mut dep_graph =
+            DepGraph::from_targets(&[target("a", &[]), target("b", &[]), target("c", &["a", "b"])])
+                .unwrap();
+        let timings = timings(&[("b", 1)]);
+
+        let path: Vec<(String, Option<Duration>)> = dep_graph
+            .scoped(&[Label::new("c")])
+            .unwrap()
+            .critical_path(&timings)
+            .into_iter()
+            .map(|(label, duration)| (label.to_string(), duration))
+            .collect();
+
+        assert_eq!(
+            vec![
+                (":b".to_string(), Some(Duration::from_secs(1))),
+                (":c".to_string(), None),
+            ],
+            path
+        );
+    }
+}
@@ -5,6 +5,12 @@ static DOT: &str = ".";
 
 static WILDCARD: &str = "//...";
 
+/// Characters, besides ASCII letters and digits, allowed in target names.
+static NAME_CHARS: &str = "_-.+@~=,";
+
+/// Characters, besides ASCII letters and digits, allowed in package paths.
+static PACKAGE_CHARS: &str = "_-.+@~";
+
 #[derive(Debug, Clone, Hash, PartialEq)]
 pub enum Label {
     Wildcard,
@@ -14,6 +20,68 @@ pub enum Label {
 
 impl Eq for Label {}
 
+/// Why a string is not a valid Label.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LabelError {
+    Empty,
+
+    /// The label has no target name, like `//a/b` or `//a/b:`.
+    MissingName {
+        label: String,
+    },
+
+    /// The label has more than one `:`, like `//a:b:c`.
+    ExtraColon {
+        label: String,
+    },
+
+    /// The package has empty, `.` or `..` parts, like `//a//b:c`.
+    InvalidPackage {
+        label: String,
+        package: String,
+    },
+
+    InvalidCharacter {
+        label: String,
+        character: char,
+    },
+}
+
+impl std::fmt::Display for LabelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabelError::Empty => write!(f, "Expected a label, but found an empty string"),
+            LabelError::MissingName { label } => write!(
+                f,
+                "Label {:?} is missing a target name. Labels look like //path/to/package:name or :name",
+                label
+            ),
+            LabelError::ExtraColon { label } => write!(
+                f,
+                "Label {:?} has more than one colon. Labels look like //path/to/package:name",
+                label
+            ),
+            LabelError::InvalidPackage { label, package } => write!(
+                f,
+                "Label {:?} has an invalid package {:?}. Packages are paths relative to the workspace root, without empty, . or .. parts",
+                label, package
+            ),
+            LabelError::InvalidCharacter { label, character } => write!(
+                f,
+                "Label {:?} has an invalid character {:?}. Package paths can only use letters, digits and any of {:?}, and target names can also use any of {:?}",
+                label, character, PACKAGE_CHARS, NAME_CHARS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LabelError {}
+
+fn invalid_character(part: &str, allowed: &str) -> Option<char> {
+    part.chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !allowed.contains(*c))
+}
+
 impl From<&str> for Label {
     fn from(name: &str) -> Label {
         Label::new(name)
@@ -47,10 +115,97 @@ impl Label {
         Label::new(&format!("//{}:{}", path.to_str().unwrap(), name))
     }
 
+    /// Parse a label, rejecting anything that isn't exactly one of:
+    ///
+    ///   * `//...`, for every target in the workspace
+    ///   * `//path/to/package:name`, where the package may be empty
+    ///   * `:name` or `name`, for a target in the current package
+    ///
+    pub fn parse(label: &str) -> Result<Label, LabelError> {
+        if label.is_empty() {
+            return Err(LabelError::Empty);
+        }
+
+        if label == WILDCARD {
+            return Ok(Label::Wildcard);
+        }
+
+        let (package, name) = match label.strip_prefix("//") {
+            Some(rest) => match rest.find(COLON) {
+                Some(colon) => (Some(&rest[..colon]), &rest[colon + 1..]),
+                None => {
+                    return Err(LabelError::MissingName {
+                        label: label.to_string(),
+                    })
+                }
+            },
+            None => (None, label.strip_prefix(COLON).unwrap_or(label)),
+        };
+
+        if name.contains(COLON) {
+            return Err(LabelError::ExtraColon {
+                label: label.to_string(),
+            });
+        }
+
+        if name.is_empty() {
+            return Err(LabelError::MissingName {
+                label: label.to_string(),
+            });
+        }
+
+        if let Some(package) = package {
+            Label::validate_package(label, package)?;
+        }
+
+        if let Some(character) = invalid_character(name, NAME_CHARS) {
+            return Err(LabelError::InvalidCharacter {
+                label: label.to_string(),
+                character,
+            });
+        }
+
+        Ok(match package {
+            Some(package) => Label::Absolute {
+                path: PathBuf::from(package),
+                name: name.to_string(),
+            },
+            None => Label::Relative {
+                name: name.to_string(),
+            },
+        })
+    }
+
+    /// Check that `package` is a valid package path within `label`.
+    pub(crate) fn validate_package(label: &str, package: &str) -> Result<(), LabelError> {
+        if package.is_empty() {
+            return Ok(());
+        }
+
+        for part in package.split('/') {
+            if part.is_empty() || part == "." || part == ".." {
+                return Err(LabelError::InvalidPackage {
+                    label: label.to_string(),
+                    package: package.to_string(),
+                });
+            }
+            if let Some(character) = invalid_character(part, PACKAGE_CHARS) {
+                return Err(LabelError::InvalidCharacter {
+                    label: label.to_string(),
+                    character,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create a label without validating it. Use `Label::parse` for labels
+    /// that come from users.
     pub fn new(name: &str) -> Label {
         let name = name.replace("\"", "");
 
-        let is_wildcard = name.starts_with("//") && name.ends_with("...");
+        let is_wildcard = name == WILDCARD;
         let is_abs_name = name.starts_with("//") && name.contains(COLON);
 
         if is_wildcard {
@@ -171,6 +326,93 @@ mod tests {
         assert_eq!(false, l3.is_all());
     }
 
+    #[test]
+    fn parses_valid_labels() {
+        assert_eq!(Ok(Label::Wildcard), Label::parse("//..."));
+        assert_eq!(
+            Ok(Label::new("//my/path:hello")),
+            Label::parse("//my/path:hello")
+        );
+        assert_eq!(Ok(Label::new(":hello")), Label::parse(":hello"));
+        assert_eq!(Ok(Label::new(":hello")), Label::parse("hello"));
+        assert_eq!(
+            Ok(Label::Absolute {
+                path: PathBuf::from(""),
+                name: "hello".to_string()
+            }),
+            Label::parse("//:hello")
+        );
+        assert_eq!(
+            Ok(Label::new("//my-lib/v1.0:hello_world+2")),
+            Label::parse("//my-lib/v1.0:hello_world+2")
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_labels() {
+        let err = |label: &str| Label::parse(label).unwrap_err();
+        assert_eq!(LabelError::Empty, err(""));
+        assert_eq!(
+            LabelError::MissingName {
+                label: "//a/b".to_string()
+            },
+            err("//a/b")
+        );
+        assert_eq!(
+            LabelError::MissingName {
+                label: "//a/b:".to_string()
+            },
+            err("//a/b:")
+        );
+        assert_eq!(
+            LabelError::MissingName {
+                label: ":".to_string()
+            },
+            err(":")
+        );
+        assert_eq!(
+            LabelError::ExtraColon {
+                label: "//a:b:c".to_string()
+            },
+            err("//a:b:c")
+        );
+        assert_eq!(
+            LabelError::InvalidPackage {
+                label: "//a//b:c".to_string(),
+                package: "a//b".to_string()
+            },
+            err("//a//b:c")
+        );
+        assert_eq!(
+            LabelError::InvalidPackage {
+                label: "//a/../b:c".to_string(),
+                package: "a/../b".to_string()
+            },
+            err("//a/../b:c")
+        );
+        assert_eq!(
+            LabelError::InvalidCharacter {
+                label: "//a b:c".to_string(),
+                character: ' '
+            },
+            err("//a b:c")
+        );
+        assert_eq!(
+            LabelError::InvalidCharacter {
+                label: "\"b\"".to_string(),
+                character: '"'
+            },
+            err("\"b\"")
+        );
+        assert_eq!(
+            LabelError::InvalidCharacter {
+                label: ":a/b".to_string(),
+                character: '/'
+            },
+            err(":a/b")
+        );
+    }
+
     #[test]
     fn parses_wildcard_path() {
         let path = "//...";
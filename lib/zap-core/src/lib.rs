@@ -1,18 +1,29 @@
 pub mod action;
+pub mod action_logs;
 pub mod archive;
+pub mod build_timings;
 pub mod buildfile;
 pub mod computed_target;
 pub mod config;
 pub mod dep_graph;
 pub mod file_scanner;
 pub mod label;
+#[cfg(target_os = "linux")]
+pub mod namespace_jail;
 pub mod parsers;
+pub mod profiler;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod read_tracer;
 pub mod rule;
 pub mod rule_config;
 pub mod rule_manager;
 pub mod rule_scanner;
 pub mod rules;
 pub mod target;
+pub mod target_pattern;
 pub mod toolchain;
 pub mod toolchain_manager;
 pub mod toolchain_scanner;
@@ -22,18 +33,29 @@ pub mod workspace;
 pub mod workspace_scanner;
 
 pub use action::*;
+pub use action_logs::*;
 pub use archive::*;
+pub use build_timings::*;
 pub use buildfile::*;
 pub use computed_target::*;
 pub use config::*;
 pub use dep_graph::*;
 pub use file_scanner::*;
 pub use label::*;
+#[cfg(target_os = "linux")]
+pub use namespace_jail::*;
+pub use profiler::*;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use read_tracer::*;
 pub use rule::*;
 pub use rule_config::*;
 pub use rule_manager::*;
 pub use rule_scanner::*;
 pub use target::*;
+pub use target_pattern::*;
 pub use toolchain::*;
 pub use toolchain_manager::*;
 pub use toolchain_scanner::*;
@@ -0,0 +1,337 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// The parts of the system that isolated actions can see, read-only.
+const SYSTEM_PATHS: &[&str] = &[
+    "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/libx32", "/usr", "/etc", "/opt",
+];
+
+/// The only devices isolated actions can use.
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/urandom", "/dev/tty"];
+
+/// A NamespaceJail isolates a process from the rest of the system using Linux
+/// user, mount and network namespaces.
+///
+/// Within the jail, the root of the filesystem is an empty, read-only tmpfs
+/// where only these paths are mounted, at the same place they are outside of
+/// it:
+///
+///   1. a read-only view of the system (`/usr`, `/etc`, ...), and a few
+///      devices like `/dev/null`
+///   2. a read-only view of every path given as `read_only`
+///   3. the `writable` path, usually the sandbox of the target
+///   4. an empty `/tmp`
+///
+/// Unless network access is allowed, the process only gets a loopback
+/// interface.
+///
+/// NOTE: `/proc` is left out on purpose: without a PID namespace of its own,
+/// the process could reach the whole filesystem through `/proc/<pid>/root`
+/// of any other process of the same user.
+///
+/// Everything is computed when the jail is created, so that `enter` can be
+/// called between `fork` and `exec`, where allocating is not safe.
+///
+#[derive(Debug)]
+pub struct NamespaceJail {
+    flags: libc::c_int,
+    new_root: CString,
+    old_root: CString,
+    cwd: CString,
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+enum Step {
+    Mkdir(CString),
+    /// Create an empty file, to bind a single file onto.
+    Touch(CString),
+    Symlink {
+        target: CString,
+        link: CString,
+    },
+    Bind {
+        src: CString,
+        dst: CString,
+        read_only: bool,
+        /// Flags of the source mount that can't be cleared from within a
+        /// user namespace, and have to be kept when remounting.
+        locked_flags: libc::c_ulong,
+    },
+    Tmpfs(CString),
+}
+
+fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl NamespaceJail {
+    pub fn new(
+        new_root: &Path,
+        writable: &Path,
+        read_only: &[PathBuf],
+        cwd: &Path,
+        allow_network: bool,
+    ) -> io::Result<NamespaceJail> {
+        std::fs::create_dir_all(new_root)?;
+
+        let mut flags = libc::CLONE_NEWUSER | libc::CLONE_NEWNS;
+        if !allow_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+
+        let uid = unsafe { libc::getuid() };
+        let gid = unsafe { libc::getgid() };
+
+        let mut jail = NamespaceJail {
+            flags,
+            new_root: cstring(new_root)?,
+            old_root: cstring(&new_root.join(".old_root"))?,
+            cwd: cstring(cwd)?,
+            uid_map: format!("{} {} 1\n", uid, uid).into_bytes(),
+            gid_map: format!("{} {} 1\n", gid, gid).into_bytes(),
+            steps: vec![],
+        };
+
+        let mut created: Vec<PathBuf> = vec![];
+        for path in SYSTEM_PATHS.iter().map(Path::new) {
+            match std::fs::symlink_metadata(path) {
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    // NOTE: on merged-usr systems `/bin` links to `usr/bin`,
+                    // so the link is recreated instead of mounting what it
+                    // points to twice.
+                    let target = std::fs::read_link(path)?;
+                    jail.steps.push(Step::Symlink {
+                        target: cstring(&target)?,
+                        link: cstring(&new_root.join(path.strip_prefix("/").unwrap()))?,
+                    });
+                }
+                Ok(meta) if meta.is_dir() => jail.bind(&mut created, new_root, path, true)?,
+                _ => (),
+            }
+        }
+
+        for device in DEVICES.iter().map(Path::new) {
+            if device.exists() {
+                jail.bind(&mut created, new_root, device, false)?;
+            }
+        }
+
+        // NOTE: mounted before anything else, since the paths to bind may
+        // well be within `/tmp` themselves.
+        let tmp = new_root.join("tmp");
+        jail.mkdir_all(&mut created, new_root, &tmp)?;
+        jail.steps.push(Step::Tmpfs(cstring(&tmp)?));
+
+        for path in read_only {
+            if path.is_dir() {
+                jail.bind(&mut created, new_root, path, true)?;
+            }
+        }
+
+        jail.bind(&mut created, new_root, writable, false)?;
+
+        jail.mkdir_all(&mut created, new_root, &new_root.join(".old_root"))?;
+
+        Ok(jail)
+    }
+
+    fn mkdir_all(
+        &mut self,
+        created: &mut Vec<PathBuf>,
+        new_root: &Path,
+        dir: &Path,
+    ) -> io::Result<()> {
+        let relative = dir.strip_prefix(new_root).unwrap_or(dir);
+        let mut path = new_root.to_path_buf();
+        for component in relative.components() {
+            if let std::path::Component::Normal(part) = component {
+                path.push(part);
+                if !created.contains(&path) {
+                    created.push(path.clone());
+                    self.steps.push(Step::Mkdir(cstring(&path)?));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn bind(
+        &mut self,
+        created: &mut Vec<PathBuf>,
+        new_root: &Path,
+        src: &Path,
+        read_only: bool,
+    ) -> io::Result<()> {
+        let dst = new_root.join(src.strip_prefix("/").unwrap_or(src));
+        if src.is_dir() {
+            self.mkdir_all(created, new_root, &dst)?;
+        } else {
+            self.mkdir_all(created, new_root, dst.parent().unwrap())?;
+            self.steps.push(Step::Touch(cstring(&dst)?));
+        }
+
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::statvfs(cstring(src)?.as_ptr(), &mut stat) })?;
+        let mut locked_flags = 0;
+        for (st_flag, ms_flag) in &[
+            (libc::ST_NOSUID, libc::MS_NOSUID),
+            (libc::ST_NODEV, libc::MS_NODEV),
+            (libc::ST_NOEXEC, libc::MS_NOEXEC),
+            (libc::ST_NOATIME, libc::MS_NOATIME),
+            (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+            (libc::ST_RELATIME, libc::MS_RELATIME),
+            (libc::ST_RDONLY, libc::MS_RDONLY),
+        ] {
+            if stat.f_flag & st_flag != 0 {
+                locked_flags |= ms_flag;
+            }
+        }
+
+        self.steps.push(Step::Bind {
+            src: cstring(src)?,
+            dst: cstring(&dst)?,
+            read_only,
+            locked_flags,
+        });
+        Ok(())
+    }
+
+    /// Move the current process into the jail.
+    ///
+    /// This is meant to be called right before `exec`, and only makes
+    /// syscalls with values computed beforehand.
+    pub fn enter(&self) -> io::Result<()> {
+        let null = std::ptr::null();
+        unsafe {
+            check(libc::unshare(self.flags))?;
+
+            // NOTE: we map our own user and group, so that files created in the
+            // jail are still owned by us outside of it.
+            NamespaceJail::write_file(b"/proc/self/setgroups\0", b"deny")?;
+            NamespaceJail::write_file(b"/proc/self/uid_map\0", &self.uid_map)?;
+            NamespaceJail::write_file(b"/proc/self/gid_map\0", &self.gid_map)?;
+
+            // keep every mount we make from leaking back into the host
+            check(libc::mount(
+                null,
+                b"/\0".as_ptr() as *const libc::c_char,
+                null,
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+
+            check(libc::mount(
+                b"tmpfs\0".as_ptr() as *const libc::c_char,
+                self.new_root.as_ptr(),
+                b"tmpfs\0".as_ptr() as *const libc::c_char,
+                0,
+                std::ptr::null(),
+            ))?;
+
+            for step in &self.steps {
+                match step {
+                    Step::Mkdir(dir) => {
+                        // NOTE: paths within bound directories already exist
+                        if libc::mkdir(dir.as_ptr(), 0o755) == -1
+                            && io::Error::last_os_error().raw_os_error() != Some(libc::EEXIST)
+                        {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                    Step::Touch(file) => {
+                        let fd = libc::open(
+                            file.as_ptr(),
+                            libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                            0o644,
+                        );
+                        check(fd)?;
+                        libc::close(fd);
+                    }
+                    Step::Symlink { target, link } => {
+                        check(libc::symlink(target.as_ptr(), link.as_ptr()))?
+                    }
+                    Step::Tmpfs(dir) => check(libc::mount(
+                        b"tmpfs\0".as_ptr() as *const libc::c_char,
+                        dir.as_ptr(),
+                        b"tmpfs\0".as_ptr() as *const libc::c_char,
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        std::ptr::null(),
+                    ))?,
+                    Step::Bind {
+                        src,
+                        dst,
+                        read_only,
+                        locked_flags,
+                    } => {
+                        check(libc::mount(
+                            src.as_ptr(),
+                            dst.as_ptr(),
+                            null,
+                            libc::MS_BIND | libc::MS_REC,
+                            std::ptr::null(),
+                        ))?;
+                        if *read_only {
+                            check(libc::mount(
+                                null,
+                                dst.as_ptr(),
+                                null,
+                                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | locked_flags,
+                                std::ptr::null(),
+                            ))?;
+                        }
+                    }
+                }
+            }
+
+            check(libc::syscall(
+                libc::SYS_pivot_root,
+                self.new_root.as_ptr(),
+                self.old_root.as_ptr(),
+            ) as libc::c_int)?;
+            check(libc::chdir(b"/\0".as_ptr() as *const libc::c_char))?;
+            check(libc::umount2(
+                b"/.old_root\0".as_ptr() as *const libc::c_char,
+                libc::MNT_DETACH,
+            ))?;
+            check(libc::rmdir(b"/.old_root\0".as_ptr() as *const libc::c_char))?;
+
+            // nothing but the writable paths and /tmp can be changed
+            check(libc::mount(
+                null,
+                b"/\0".as_ptr() as *const libc::c_char,
+                null,
+                libc::MS_REMOUNT | libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                std::ptr::null(),
+            ))?;
+
+            check(libc::chdir(self.cwd.as_ptr()))?;
+        }
+        Ok(())
+    }
+
+    unsafe fn write_file(path: &[u8], contents: &[u8]) -> io::Result<()> {
+        let fd = libc::open(path.as_ptr() as *const libc::c_char, libc::O_WRONLY);
+        check(fd)?;
+        let written = libc::write(fd, contents.as_ptr() as *const libc::c_void, contents.len());
+        libc::close(fd);
+        if written < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
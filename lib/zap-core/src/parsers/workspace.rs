@@ -2,6 +2,7 @@ use crate::*;
 use anyhow::Context;
 use log::*;
 use std::path::PathBuf;
+use std::time::Duration;
 
 pub fn parse(
     toml: toml::Value,
@@ -18,7 +19,14 @@ pub fn parse(
         .context("Workspace name field must be a string")?
         .to_string();
 
-    let workspace = Workspace::new(name, root)?;
+    let exec_policy = if let Some(actions) = toml.get("actions") {
+        let table = actions.as_table().context(format!("Expected the [actions] section in your Workspace.toml to be a TOML table, but instead found a {}", actions.type_str()))?;
+        parse_exec_policy(table)?
+    } else {
+        ExecPolicy::default()
+    };
+
+    let workspace = Workspace::new(name, root)?.with_exec_policy(exec_policy);
 
     let toolchain_archives = if let Some(toolchains) = toml.get("toolchains") {
         let table = toolchains.as_table().context(format!("Expected the [toolchains] section in your Workspace.toml to be a TOML table, but instead found a {}", toolchains.type_str()))?;
@@ -36,6 +44,107 @@ pub fn parse(
     Ok(workspace)
 }
 
+pub fn parse_exec_policy(actions: &toml::value::Table) -> Result<ExecPolicy, anyhow::Error> {
+    let inherit_env = match actions.get("inherit_env") {
+        None => vec![],
+        Some(value) => value
+            .as_array()
+            .and_then(|vars| {
+                vars.iter()
+                    .map(|var| var.as_str().map(|var| var.to_string()))
+                    .collect::<Option<Vec<String>>>()
+            })
+            .context(format!(
+                "Expected actions.inherit_env in your Workspace.toml to be a list of environment variable names, but instead found {}",
+                value
+            ))?,
+    };
+
+    let isolation = match actions.get("sandbox") {
+        None => Isolation::None,
+        Some(toml::Value::String(isolation)) => isolation
+            .parse()
+            .context("Invalid actions.sandbox in your Workspace.toml")?,
+        Some(value) => {
+            return Err(anyhow::anyhow!(
+                "Expected actions.sandbox in your Workspace.toml to be one of \"none\" or \"namespaces\", but instead found {}",
+                value
+            ))
+        }
+    };
+
+    let flag = |key: &str| -> Result<bool, anyhow::Error> {
+        match actions.get(key) {
+            None => Ok(false),
+            Some(value) => value.as_bool().context(format!(
+                "Expected actions.{} in your Workspace.toml to be true or false, but instead found {}",
+                key, value
+            )),
+        }
+    };
+
+    Ok(ExecPolicy {
+        limits: parse_action_limits(actions)?,
+        inherit_env,
+        isolation,
+        allow_network: flag("network")?,
+        read_only_paths: vec![],
+        strict: flag("strict")?,
+        workspace_root: None,
+    })
+}
+
+pub fn parse_action_limits(actions: &toml::value::Table) -> Result<ActionLimits, anyhow::Error> {
+    let seconds = |key: &str| -> Result<Option<u64>, anyhow::Error> {
+        match actions.get(key) {
+            None => Ok(None),
+            Some(value) => value
+                .as_integer()
+                .filter(|n| *n > 0)
+                .map(|n| Some(n as u64))
+                .context(format!(
+                    "Expected actions.{} in your Workspace.toml to be a positive number of seconds, but instead found {}",
+                    key, value
+                )),
+        }
+    };
+
+    let address_space = match actions.get("max_memory") {
+        None => None,
+        Some(toml::Value::Integer(n)) if *n > 0 => Some(*n as u64),
+        Some(toml::Value::String(size)) => Some(parse_size(size).map_err(|err| {
+            anyhow::anyhow!("Invalid actions.max_memory in your Workspace.toml: {}", err)
+        })?),
+        Some(value) => {
+            return Err(anyhow::anyhow!(
+                "Expected actions.max_memory in your Workspace.toml to be a size like \"4G\", but instead found {}",
+                value
+            ))
+        }
+    };
+
+    let open_files = match actions.get("max_open_files") {
+        None => None,
+        Some(value) => Some(
+            value
+                .as_integer()
+                .filter(|n| *n > 0)
+                .map(|n| n as u64)
+                .context(format!(
+                    "Expected actions.max_open_files in your Workspace.toml to be a positive number, but instead found {}",
+                    value
+                ))?,
+        ),
+    };
+
+    Ok(ActionLimits {
+        timeout: seconds("timeout")?.map(Duration::from_secs),
+        cpu_time: seconds("max_cpu_time")?,
+        address_space,
+        open_files,
+    })
+}
+
 pub fn parse_archives(archives: &toml::value::Table) -> Result<Vec<Archive>, anyhow::Error> {
     let mut t = vec![];
     for (name, config) in archives {
@@ -112,6 +221,85 @@ name = "tiny_lib"
         assert_eq!(workspace.name(), "tiny_lib");
     }
 
+    #[test]
+    fn parses_exec_policy() {
+        let toml: toml::Value = r#"
+[workspace]
+name = "tiny_lib"
+
+[actions]
+timeout = 300
+max_cpu_time = 600
+max_memory = "4G"
+max_open_files = 1024
+inherit_env = ["HOME", "LANG"]
+        "#
+        .parse::<toml::Value>()
+        .unwrap();
+        let workspace = parse(toml, &PathBuf::from("."), &ToolchainManager::default()).unwrap();
+        assert_eq!(
+            &ExecPolicy {
+                limits: ActionLimits {
+                    timeout: Some(Duration::from_secs(300)),
+                    cpu_time: Some(600),
+                    address_space: Some(4 << 30),
+                    open_files: Some(1024),
+                },
+                inherit_env: vec!["HOME".to_string(), "LANG".to_string()],
+                ..ExecPolicy::default()
+            },
+            workspace.exec_policy()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_action_limits() {
+        let toml: toml::Value = r#"
+[workspace]
+name = "tiny_lib"
+
+[actions]
+timeout = "5 minutes"
+        "#
+        .parse::<toml::Value>()
+        .unwrap();
+        let err = parse(toml, &PathBuf::from("."), &ToolchainManager::default()).unwrap_err();
+        assert_eq!(
+            "Expected actions.timeout in your Workspace.toml to be a positive number of seconds, but instead found \"5 minutes\"",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn parses_action_sandboxing() {
+        let toml: toml::Value = r#"
+[workspace]
+name = "tiny_lib"
+
+[actions]
+sandbox = "namespaces"
+network = true
+strict = true
+        "#
+        .parse::<toml::Value>()
+        .unwrap();
+        let workspace = parse(toml, &PathBuf::from("."), &ToolchainManager::default()).unwrap();
+        assert_eq!(Isolation::Namespaces, workspace.exec_policy().isolation);
+        assert!(workspace.exec_policy().allow_network);
+        assert!(workspace.exec_policy().strict);
+
+        let toml: toml::Value = r#"
+[workspace]
+name = "tiny_lib"
+
+[actions]
+sandbox = "chroot"
+        "#
+        .parse::<toml::Value>()
+        .unwrap();
+        assert!(parse(toml, &PathBuf::from("."), &ToolchainManager::default()).is_err());
+    }
+
     #[test]
     fn allows_for_custom_toolchains() {
         let toml: toml::Value = r#"
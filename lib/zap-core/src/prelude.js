@@ -21,6 +21,13 @@ const err = x => { throw new Error(x); };
 const label = () => "label";
 const string = () => "string";
 const file = () => "file";
+const bool = () => "bool";
+const int = () => "int";
+const dict = (keys = string()) => {
+  if (keys === string()) return "string_dict";
+  if (keys === label()) return "label_keyed_dict";
+  err(`Dictionaries can only be keyed by   string()   or   label()  , instead found: ${keys}`);
+};
 
 const console = {
   log: (...args) => ffi("console.log", args)
@@ -80,7 +87,7 @@ Zap.Targets.compute = target => {
 
     action: () => ({
       declareOutputs: outs => ffi("Zap.Targets.compute::ctx.actions.declareOutputs", {label, outs}),
-      exec: ({cmd, args, cwd}) => ffi("Zap.Targets.compute::ctx.actions.exec", {label, cmd, args, cwd}),
+      exec: ({cmd, args, cwd, env, timeout}) => ffi("Zap.Targets.compute::ctx.actions.exec", {label, cmd, args, cwd, env, timeout}),
       copy: ({src, dst}) => ffi("Zap.Targets.compute::ctx.actions.copy", {label, src, dst}),
       writeFile: ({data, dst}) => ffi("Zap.Targets.compute::ctx.actions.writeFile", {label, data, dst}),
     }),
@@ -0,0 +1,152 @@
+use anyhow::Context;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// A Profiler records how long the different phases of a build take, so they
+/// can be exported as a Chrome Trace Event file, and inspected in
+/// chrome://tracing or Perfetto.
+///
+/// Profilers are disabled by default, in which case recording spans does
+/// nothing. Clones of a Profiler record into the same trace, so it can be
+/// shared across threads.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    trace: Option<Arc<Trace>>,
+}
+
+#[derive(Debug)]
+struct Trace {
+    start: Instant,
+    events: Mutex<Vec<serde_json::Value>>,
+}
+
+/// A Span measures a single phase, from its creation until it is dropped.
+pub struct Span {
+    trace: Option<Arc<Trace>>,
+    name: String,
+    category: &'static str,
+    label: Option<String>,
+    start: Instant,
+}
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_ID: u64 = NEXT_THREAD_ID.fetch_add(1, Ordering::SeqCst);
+}
+
+impl Profiler {
+    pub fn enabled() -> Profiler {
+        Profiler {
+            trace: Some(Arc::new(Trace {
+                start: Instant::now(),
+                events: Mutex::new(vec![]),
+            })),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Start measuring a phase. The phase ends when the Span is dropped.
+    pub fn span(&self, category: &'static str, name: &str) -> Span {
+        Span {
+            trace: self.trace.clone(),
+            name: name.to_string(),
+            category,
+            label: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Start measuring a phase of the build of a given target.
+    pub fn target_span(&self, category: &'static str, name: &str, label: &str) -> Span {
+        let mut span = self.span(category, name);
+        span.label = Some(label.to_string());
+        span
+    }
+
+    /// Write every recorded span as a Chrome Trace Event file.
+    pub fn write(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let trace = serde_json::json!({
+            "traceEvents": self.events(),
+            "displayTimeUnit": "ms",
+        });
+        std::fs::write(path, trace.to_string())
+            .context(format!("Could not write profile to {:?}", path))
+    }
+
+    fn events(&self) -> Vec<serde_json::Value> {
+        match &self.trace {
+            Some(trace) => trace.events.lock().unwrap().clone(),
+            None => vec![],
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let trace = match &self.trace {
+            Some(trace) => trace,
+            None => return,
+        };
+
+        let mut event = serde_json::json!({
+            "name": match &self.label {
+                Some(label) => format!("{} {}", self.name, label),
+                None => self.name.clone(),
+            },
+            "cat": self.category,
+            "ph": "X",
+            "ts": self.start.duration_since(trace.start).as_micros() as u64,
+            "dur": self.start.elapsed().as_micros() as u64,
+            "pid": std::process::id(),
+            "tid": THREAD_ID.with(|id| *id),
+        });
+        if let Some(label) = &self.label {
+            event["args"] = serde_json::json!({ "label": label });
+        }
+
+        trace.events.lock().unwrap().push(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_profilers_record_nothing() {
+        let profiler = Profiler::default();
+        drop(profiler.span("build", "load"));
+        assert!(!profiler.is_enabled());
+        assert!(profiler.events().is_empty());
+    }
+
+    #[test]
+    fn records_complete_events_for_spans() {
+        let profiler = Profiler::enabled();
+        {
+            let _load = profiler.span("load", "scan");
+            let _seal = profiler.clone().target_span("build", "seal", "//a:a");
+        }
+
+        let events = profiler.events();
+        assert_eq!(2, events.len());
+
+        // inner spans are dropped first
+        assert_eq!("seal //a:a", events[0]["name"]);
+        assert_eq!("build", events[0]["cat"]);
+        assert_eq!("X", events[0]["ph"]);
+        assert_eq!("//a:a", events[0]["args"]["label"]);
+
+        assert_eq!("scan", events[1]["name"]);
+        assert_eq!("load", events[1]["cat"]);
+        assert!(events[1]["ts"].as_u64().unwrap() <= events[0]["ts"].as_u64().unwrap());
+        assert!(events[1]["dur"].as_u64().unwrap() >= events[0]["dur"].as_u64().unwrap());
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::FileExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+/// The register set holding the general purpose registers.
+const NT_PRSTATUS: libc::c_int = 1;
+
+const TRACE_OPTIONS: libc::c_int = libc::PTRACE_O_TRACESYSGOOD
+    | libc::PTRACE_O_TRACEFORK
+    | libc::PTRACE_O_TRACEVFORK
+    | libc::PTRACE_O_TRACECLONE
+    | libc::PTRACE_O_TRACEEXEC
+    | libc::PTRACE_O_EXITKILL;
+
+/// A ReadTracer follows a process and all of its children with `ptrace`, and
+/// collects the paths of every file they successfully open for reading.
+///
+/// Only `open`, `openat` and `openat2` calls are looked at. Relative paths are
+/// made absolute using the working directory of the process, or the
+/// directory they were opened from, at the time of the call.
+///
+/// The traced process must be spawned from the thread that traces it, and
+/// any process it leaves running when it exits is killed.
+///
+#[derive(Debug)]
+pub struct ReadTracer {
+    pid: libc::pid_t,
+
+    /// The processes being traced.
+    traced: HashSet<libc::pid_t>,
+
+    /// The processes stopped within a syscall, and the path they are
+    /// opening, if they are opening one for reading.
+    in_syscall: HashMap<libc::pid_t, Option<PathBuf>>,
+
+    reads: BTreeSet<PathBuf>,
+}
+
+fn check(result: libc::c_long) -> io::Result<libc::c_long> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl ReadTracer {
+    /// Set up a command to be traced from the moment it execs.
+    pub fn trace(cmd: &mut Command) {
+        unsafe {
+            cmd.pre_exec(|| {
+                check(libc::ptrace(libc::PTRACE_TRACEME, 0, 0, 0))?;
+                Ok(())
+            });
+        }
+    }
+
+    pub fn new(pid: u32) -> ReadTracer {
+        ReadTracer {
+            pid: pid as libc::pid_t,
+            traced: HashSet::new(),
+            in_syscall: HashMap::new(),
+            reads: BTreeSet::new(),
+        }
+    }
+
+    /// The files read so far.
+    pub fn reads(&self) -> &BTreeSet<PathBuf> {
+        &self.reads
+    }
+
+    /// Trace the process until it exits. If it is still running after
+    /// `timeout`, it is killed and `None` is returned.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Option<ExitStatus>> {
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let exited = Arc::new(Mutex::new(false));
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        if let Some(timeout) = timeout {
+            let pid = self.pid;
+            let timed_out = timed_out.clone();
+            let exited = exited.clone();
+            std::thread::spawn(move || {
+                if let Err(mpsc::RecvTimeoutError::Timeout) = done_rx.recv_timeout(timeout) {
+                    // NOTE: the process is only reaped while holding this
+                    // lock, so if it hasn't exited yet its pid can't have
+                    // been reused by another process.
+                    let exited = exited.lock().unwrap();
+                    if !*exited {
+                        timed_out.store(true, Ordering::SeqCst);
+                        unsafe { libc::kill(pid, libc::SIGKILL) };
+                    }
+                }
+            });
+        }
+
+        let status = self.trace_until_exit(&exited);
+        drop(done_tx);
+        let status = status?;
+
+        if timed_out.load(Ordering::SeqCst) {
+            Ok(None)
+        } else {
+            Ok(Some(status))
+        }
+    }
+
+    fn trace_until_exit(&mut self, exited: &Mutex<bool>) -> io::Result<ExitStatus> {
+        loop {
+            let (pid, status) = self.wait_next(exited)?;
+
+            if libc::WIFEXITED(status) || libc::WIFSIGNALED(status) {
+                self.traced.remove(&pid);
+                self.in_syscall.remove(&pid);
+                if pid == self.pid {
+                    self.kill_leftovers();
+                    return Ok(ExitStatus::from_raw(status));
+                }
+                continue;
+            }
+
+            if !libc::WIFSTOPPED(status) {
+                continue;
+            }
+
+            let signal = libc::WSTOPSIG(status);
+            let event = status >> 16;
+            let forward_signal = if self.traced.insert(pid) {
+                // NOTE: the first stop of the traced process is right after
+                // its exec, and the first stop of its children is a SIGSTOP.
+                unsafe { libc::ptrace(libc::PTRACE_SETOPTIONS, pid, 0, TRACE_OPTIONS) };
+                0
+            } else if signal == libc::SIGTRAP | 0x80 {
+                self.on_syscall(pid);
+                0
+            } else if event != 0 {
+                0
+            } else {
+                signal
+            };
+
+            unsafe { libc::ptrace(libc::PTRACE_SYSCALL, pid, 0, forward_signal) };
+        }
+    }
+
+    /// Wait for the next change of state of any of the traced processes.
+    ///
+    /// We wait without reaping first, and only reap while holding `exited`,
+    /// so it is set before the pid of the traced process can be reused.
+    fn wait_next(&self, exited: &Mutex<bool>) -> io::Result<(libc::pid_t, libc::c_int)> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // NOTE: __WNOTHREAD keeps us from reaping the children of other
+        // threads, that may be running other actions.
+        check(unsafe {
+            libc::waitid(
+                libc::P_ALL,
+                0,
+                &mut info,
+                libc::WEXITED | libc::WSTOPPED | libc::WNOWAIT | libc::__WALL | libc::__WNOTHREAD,
+            )
+        } as libc::c_long)?;
+        let pid = unsafe { info.si_pid() };
+
+        let mut exited = exited.lock().unwrap();
+        let mut status = 0;
+        check(
+            unsafe { libc::waitpid(pid, &mut status, libc::__WALL | libc::__WNOTHREAD) }
+                as libc::c_long,
+        )?;
+        if pid == self.pid && (libc::WIFEXITED(status) || libc::WIFSIGNALED(status)) {
+            *exited = true;
+        }
+
+        Ok((pid, status))
+    }
+
+    fn kill_leftovers(&mut self) {
+        for pid in self.traced.drain() {
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+        let mut status = 0;
+        while unsafe { libc::waitpid(-1, &mut status, libc::__WALL | libc::__WNOTHREAD) } > 0 {}
+    }
+
+    fn on_syscall(&mut self, pid: libc::pid_t) {
+        let regs = match ReadTracer::registers(pid) {
+            Ok(regs) => regs,
+            Err(_) => return,
+        };
+
+        match self.in_syscall.remove(&pid) {
+            Some(Some(path)) => {
+                if syscall::return_value(&regs) >= 0 {
+                    self.reads.insert(path);
+                }
+            }
+            Some(None) => (),
+            None => {
+                let path = ReadTracer::opened_for_reading(pid, &regs).unwrap_or(None);
+                self.in_syscall.insert(pid, path);
+            }
+        }
+    }
+
+    /// The path being opened, if the process is entering a syscall that
+    /// opens a file for reading.
+    fn opened_for_reading(
+        pid: libc::pid_t,
+        regs: &libc::user_regs_struct,
+    ) -> io::Result<Option<PathBuf>> {
+        let (nr, args) = syscall::number_and_args(regs);
+        let (dirfd, path, flags) = match nr {
+            #[cfg(target_arch = "x86_64")]
+            libc::SYS_open => (libc::AT_FDCWD, args[0], args[1]),
+            libc::SYS_openat => (args[0] as libc::c_int, args[1], args[2]),
+            libc::SYS_openat2 => {
+                // NOTE: the flags are the first field of the `open_how` struct
+                let mut flags = [0; 8];
+                ReadTracer::memory(pid)?.read_exact_at(&mut flags, args[2])?;
+                (args[0] as libc::c_int, args[1], u64::from_ne_bytes(flags))
+            }
+            _ => return Ok(None),
+        };
+
+        if flags as libc::c_int & libc::O_ACCMODE == libc::O_WRONLY {
+            return Ok(None);
+        }
+
+        let path = ReadTracer::read_path(pid, path)?;
+        if path.is_absolute() {
+            return Ok(Some(path));
+        }
+
+        let dir = if dirfd == libc::AT_FDCWD {
+            std::fs::read_link(format!("/proc/{}/cwd", pid))?
+        } else {
+            std::fs::read_link(format!("/proc/{}/fd/{}", pid, dirfd))?
+        };
+        Ok(Some(dir.join(path)))
+    }
+
+    fn registers(pid: libc::pid_t) -> io::Result<libc::user_regs_struct> {
+        let mut regs: libc::user_regs_struct = unsafe { std::mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: &mut regs as *mut _ as *mut libc::c_void,
+            iov_len: std::mem::size_of::<libc::user_regs_struct>(),
+        };
+        check(unsafe { libc::ptrace(libc::PTRACE_GETREGSET, pid, NT_PRSTATUS, &mut iov) })?;
+        Ok(regs)
+    }
+
+    fn memory(pid: libc::pid_t) -> io::Result<File> {
+        File::open(format!("/proc/{}/mem", pid))
+    }
+
+    /// Read a NUL-terminated path from the memory of a process.
+    fn read_path(pid: libc::pid_t, mut addr: u64) -> io::Result<PathBuf> {
+        let memory = ReadTracer::memory(pid)?;
+        let mut path = vec![];
+        let mut chunk = [0; 256];
+        while path.len() < libc::PATH_MAX as usize {
+            // NOTE: chunks never cross a page boundary, since the next page
+            // may not be mapped.
+            let len = chunk.len().min(4096 - (addr % 4096) as usize);
+            let read = memory.read_at(&mut chunk[..len], addr)?;
+            if read == 0 {
+                break;
+            }
+            if let Some(end) = chunk[..read].iter().position(|byte| *byte == 0) {
+                path.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            path.extend_from_slice(&chunk[..read]);
+            addr += read as u64;
+        }
+        Ok(PathBuf::from(OsString::from_vec(path)))
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod syscall {
+    pub fn number_and_args(regs: &libc::user_regs_struct) -> (libc::c_long, [u64; 3]) {
+        (
+            regs.orig_rax as libc::c_long,
+            [regs.rdi, regs.rsi, regs.rdx],
+        )
+    }
+
+    pub fn return_value(regs: &libc::user_regs_struct) -> i64 {
+        regs.rax as i64
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod syscall {
+    pub fn number_and_args(regs: &libc::user_regs_struct) -> (libc::c_long, [u64; 3]) {
+        (
+            regs.regs[8] as libc::c_long,
+            [regs.regs[0], regs.regs[1], regs.regs[2]],
+        )
+    }
+
+    pub fn return_value(regs: &libc::user_regs_struct) -> i64 {
+        regs.regs[0] as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_the_files_read_by_a_process_and_its_children() {
+        let dir = std::env::temp_dir().join("zap-core-read-tracer");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("read"), "read").unwrap();
+        std::fs::write(dir.join("sub/read"), "read").unwrap();
+
+        let mut cmd = Command::new("sh");
+        cmd.args([
+            "-c",
+            "cat read > /dev/null; (cd sub && cat read); echo > written",
+        ])
+        .current_dir(&dir);
+        ReadTracer::trace(&mut cmd);
+        let mut tracer = ReadTracer::new(cmd.spawn().unwrap().id());
+        let status = tracer.wait(None).unwrap().unwrap();
+
+        assert!(status.success());
+        let dir = std::fs::canonicalize(&dir).unwrap();
+        assert!(tracer.reads().contains(&dir.join("read")));
+        assert!(tracer.reads().contains(&dir.join("sub/read")));
+        assert!(!tracer.reads().contains(&dir.join("written")));
+    }
+
+    #[test]
+    fn kills_traced_processes_that_time_out() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("10");
+        ReadTracer::trace(&mut cmd);
+        let mut tracer = ReadTracer::new(cmd.spawn().unwrap().id());
+        let started_at = std::time::Instant::now();
+        assert!(tracer
+            .wait(Some(Duration::from_millis(100)))
+            .unwrap()
+            .is_none());
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -2,6 +2,7 @@ use super::Label;
 use anyhow::*;
 use dashmap::DashMap;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
@@ -9,7 +10,17 @@ pub enum CfgValueType {
     String,
     Label,
     File,
+    Bool,
+    Int,
     List(Box<CfgValueType>),
+
+    /// A table of strings, like `{ d = "TEST" }`.
+    StringDict,
+
+    /// A table of strings keyed by labels, like `{ ":dep" = "dep.hrl" }`.
+    ///
+    /// The labels are dependencies of the target, like the ones in `deps`.
+    LabelKeyedDict,
 }
 
 #[derive(Debug, Clone)]
@@ -17,29 +28,70 @@ pub enum CfgValue {
     String(String),
     Label(Label),
     File(PathBuf),
+    Bool(bool),
+    Int(i64),
     List(Vec<CfgValue>),
+    StringDict(HashMap<String, String>),
+    LabelKeyedDict(HashMap<Label, String>),
 }
 
-impl From<(serde_json::Value, CfgValueType)> for CfgValue {
-    fn from(spec: (serde_json::Value, CfgValueType)) -> CfgValue {
+impl TryFrom<(serde_json::Value, CfgValueType)> for CfgValue {
+    type Error = anyhow::Error;
+
+    fn try_from(spec: (serde_json::Value, CfgValueType)) -> Result<CfgValue, anyhow::Error> {
         let (json, type_) = spec;
-        match json {
-            serde_json::Value::String(string) => match type_ {
-                CfgValueType::String => CfgValue::String(string),
-                CfgValueType::Label => CfgValue::Label(Label::new(&string)),
-                CfgValueType::File => CfgValue::File(PathBuf::from(string)),
-                CfgValueType::List(_) => panic!("Oops!"),
-            },
-            serde_json::Value::Array(parts) => match type_ {
-                CfgValueType::List(element_type) => CfgValue::List(
-                    parts
-                        .iter()
-                        .map(|e| (e.clone(), *element_type.clone()).into())
-                        .collect(),
-                ),
-                _ => panic!("oops!"),
-            },
-            _ => panic!("oops!"),
+        match (&json, &type_) {
+            (serde_json::Value::String(string), CfgValueType::String) => {
+                Ok(CfgValue::String(string.clone()))
+            }
+            (serde_json::Value::String(string), CfgValueType::Label) => Ok(CfgValue::Label(
+                Label::parse(string).context(format!("Invalid label {:?}", string))?,
+            )),
+            (serde_json::Value::String(string), CfgValueType::File) => {
+                Ok(CfgValue::File(PathBuf::from(string)))
+            }
+            (serde_json::Value::Bool(b), CfgValueType::Bool) => Ok(CfgValue::Bool(*b)),
+            (serde_json::Value::Number(n), CfgValueType::Int) => n
+                .as_i64()
+                .map(CfgValue::Int)
+                .context(format!("Expected {} to be an integer", n)),
+            (serde_json::Value::Array(parts), CfgValueType::List(element_type)) => {
+                let mut elements = vec![];
+                for part in parts {
+                    elements.push(CfgValue::try_from((part.clone(), *element_type.clone()))?);
+                }
+                Ok(CfgValue::List(elements))
+            }
+            (serde_json::Value::Object(entries), CfgValueType::StringDict)
+            | (serde_json::Value::Object(entries), CfgValueType::LabelKeyedDict) => {
+                let mut dict = vec![];
+                for (k, v) in entries {
+                    let v = v.as_str().context(format!(
+                        "Expected dictionary value {:?} to be a String but found: {}",
+                        k, v
+                    ))?;
+                    dict.push((k.clone(), v.to_string()));
+                }
+                match type_ {
+                    CfgValueType::StringDict => {
+                        Ok(CfgValue::StringDict(dict.into_iter().collect()))
+                    }
+                    _ => {
+                        let mut entries = HashMap::new();
+                        for (k, v) in dict {
+                            let label = Label::parse(&k)
+                                .context(format!("Invalid label {:?} in dictionary keys", k))?;
+                            entries.insert(label, v);
+                        }
+                        Ok(CfgValue::LabelKeyedDict(entries))
+                    }
+                }
+            }
+            _ => Err(anyhow!(
+                "Expected a value of type {:?} but found: {}",
+                type_,
+                json
+            )),
         }
     }
 }
@@ -50,9 +102,23 @@ impl Into<serde_json::Value> for CfgValue {
             CfgValue::String(string) => serde_json::Value::String(string),
             CfgValue::Label(label) => serde_json::Value::String(label.to_string()),
             CfgValue::File(path) => serde_json::Value::String(path.to_str().unwrap().to_string()),
+            CfgValue::Bool(b) => serde_json::Value::Bool(b),
+            CfgValue::Int(i) => serde_json::Value::Number(i.into()),
             CfgValue::List(parts) => {
                 serde_json::Value::Array(parts.iter().map(|e| e.clone().into()).collect())
             }
+            CfgValue::StringDict(entries) => serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, serde_json::Value::String(v)))
+                    .collect(),
+            ),
+            CfgValue::LabelKeyedDict(entries) => serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), serde_json::Value::String(v)))
+                    .collect(),
+            ),
         }
     }
 }
@@ -186,3 +252,58 @@ impl Into<serde_json::Value> for RuleConfig {
         serde_json::Value::Object(map)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(json: serde_json::Value, type_: CfgValueType) -> serde_json::Value {
+        CfgValue::try_from((json, type_)).unwrap().into()
+    }
+
+    #[test]
+    fn converts_values_to_and_from_json() {
+        assert_eq!(json!(true), round_trip(json!(true), CfgValueType::Bool));
+        assert_eq!(json!(-2), round_trip(json!(-2), CfgValueType::Int));
+        assert_eq!(
+            json!([1, 2]),
+            round_trip(
+                json!([1, 2]),
+                CfgValueType::List(Box::new(CfgValueType::Int))
+            )
+        );
+        assert_eq!(
+            json!({ "d": "TEST", "x": "" }),
+            round_trip(json!({ "d": "TEST", "x": "" }), CfgValueType::StringDict)
+        );
+        assert_eq!(
+            json!({ "//a:b": "b.hrl", ":c": "c.hrl" }),
+            round_trip(
+                json!({ "//a:b": "b.hrl", ":c": "c.hrl" }),
+                CfgValueType::LabelKeyedDict
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        assert!(CfgValue::try_from((json!("true"), CfgValueType::Bool)).is_err());
+        assert!(CfgValue::try_from((json!(1.5), CfgValueType::Int)).is_err());
+        assert!(CfgValue::try_from((
+            json!(["a", 1]),
+            CfgValueType::List(Box::new(CfgValueType::String))
+        ))
+        .is_err());
+        assert!(CfgValue::try_from((json!({ "d": 1 }), CfgValueType::StringDict)).is_err());
+        assert!(CfgValue::try_from((json!(null), CfgValueType::String)).is_err());
+        assert!(CfgValue::try_from((json!(""), CfgValueType::Label)).is_err());
+
+        let err = CfgValue::try_from((json!({ "//a:b:c": "c.hrl" }), CfgValueType::LabelKeyedDict))
+            .unwrap_err();
+        assert_eq!(
+            "Invalid label \"//a:b:c\" in dictionary keys",
+            err.to_string()
+        );
+    }
+}
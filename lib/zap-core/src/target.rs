@@ -1,4 +1,5 @@
-use super::{Archive, Label, Rule, RuleConfig};
+use super::{Archive, CfgValue, Label, Rule, RuleConfig};
+use std::collections::HashMap;
 
 /// A Target in the Zap dependency graph is a labeled instantiation of a rule plus a configuration
 /// object.
@@ -67,6 +68,26 @@ impl Target {
             .iter()
             .map(|dep| dep.canonicalize(&label.path()))
             .collect();
+
+        // NOTE: the keys of label keyed dicts are dependencies too, so they
+        // are resolved relative to the package just like deps are.
+        let mut dict_deps = vec![];
+        for mut entry in cfg.as_map().iter_mut() {
+            if let CfgValue::LabelKeyedDict(entries) = entry.value_mut() {
+                let resolved: HashMap<Label, String> = entries
+                    .drain()
+                    .map(|(key, value)| (key.canonicalize(&label.path()), value))
+                    .collect();
+                dict_deps.extend(resolved.keys().cloned());
+                *entries = resolved;
+            }
+        }
+        dict_deps.sort_by_key(|dep| dep.to_string());
+        for dep in dict_deps {
+            if !deps.contains(&dep) {
+                deps.push(dep);
+            }
+        }
         deps.extend_from_slice(rule.toolchains());
 
         Target::Local(LocalTarget {
@@ -173,4 +194,39 @@ mod tests {
         assert_eq!(1, target.deps().len());
         assert_eq!(Label::new("dep"), target.deps()[0]);
     }
+
+    #[test]
+    fn includes_label_keyed_dict_keys_in_dependencies() {
+        let rule = Rule::new(
+            "test_rule".to_string(),
+            "TestRule".to_string(),
+            vec![],
+            ConfigSpec::default(),
+            RuleConfig::default(),
+        );
+        let cfg = RuleConfig::default();
+        cfg.insert(
+            "deps".to_string(),
+            CfgValue::List(vec![CfgValue::Label(Label::new(":b"))]),
+        );
+        let mut headers = HashMap::new();
+        headers.insert(Label::new(":b"), "b.hrl".to_string());
+        headers.insert(Label::new("//c:c"), "c.hrl".to_string());
+        cfg.insert("headers".to_string(), CfgValue::LabelKeyedDict(headers));
+
+        let target = Target::local(Label::new("//a:a"), &rule, cfg);
+        assert_eq!(
+            vec![Label::new("//a:b"), Label::new("//c:c")],
+            target.deps()
+        );
+        match target.config().get("headers") {
+            Some(CfgValue::LabelKeyedDict(headers)) => {
+                assert_eq!(
+                    Some(&"b.hrl".to_string()),
+                    headers.get(&Label::new("//a:b"))
+                )
+            }
+            value => panic!("Expected a LabelKeyedDict but found {:?}", value),
+        }
+    }
 }
@@ -0,0 +1,194 @@
+use super::{Label, LabelError};
+use std::path::{Path, PathBuf};
+
+static RECURSIVE_SUFFIX: &str = "...";
+static ALL_TARGETS: &[&str] = &["all", "*"];
+
+/// A TargetPattern selects targets in the workspace by their labels:
+///
+///   * `//...` selects every target, and `//a/...` every target in the `a`
+///     package and in the packages below it
+///   * `//a:all` or `//a:*` selects every target in the `a` package
+///   * `//a:b` selects a single target
+///
+/// Prefixing a pattern with `-`, like `-//a/b/...`, excludes the targets it
+/// selects instead. Patterns are applied in order, so an exclusion only
+/// removes targets selected by the patterns before it.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetPattern {
+    Target(Label),
+    Package(PathBuf),
+    Recursive(PathBuf),
+    Exclude(Box<TargetPattern>),
+}
+
+impl std::fmt::Display for TargetPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetPattern::Target(label) => write!(f, "{}", label.to_string()),
+            TargetPattern::Package(path) => write!(f, "//{}:all", path.display()),
+            TargetPattern::Recursive(path) if path.as_os_str().is_empty() => write!(f, "//..."),
+            TargetPattern::Recursive(path) => write!(f, "//{}/...", path.display()),
+            TargetPattern::Exclude(pattern) => write!(f, "-{}", pattern),
+        }
+    }
+}
+
+impl TargetPattern {
+    pub fn parse(pattern: &str) -> Result<TargetPattern, LabelError> {
+        if let Some(excluded) = pattern.strip_prefix('-') {
+            return match TargetPattern::parse(excluded)? {
+                TargetPattern::Exclude(_) => Err(LabelError::InvalidCharacter {
+                    label: pattern.to_string(),
+                    character: '-',
+                }),
+                excluded => Ok(TargetPattern::Exclude(Box::new(excluded))),
+            };
+        }
+
+        if let Some(package) = pattern
+            .strip_prefix("//")
+            .and_then(|rest| rest.strip_suffix(RECURSIVE_SUFFIX))
+        {
+            let package = match package {
+                "" => "",
+                _ => package
+                    .strip_suffix('/')
+                    .ok_or_else(|| LabelError::InvalidPackage {
+                        label: pattern.to_string(),
+                        package: package.to_string(),
+                    })?,
+            };
+            Label::validate_package(pattern, package)?;
+            return Ok(TargetPattern::Recursive(PathBuf::from(package)));
+        }
+
+        if let Some((package, name)) = pattern
+            .strip_prefix("//")
+            .and_then(|rest| rest.split_once(':'))
+        {
+            if ALL_TARGETS.contains(&name) {
+                Label::validate_package(pattern, package)?;
+                return Ok(TargetPattern::Package(PathBuf::from(package)));
+            }
+        }
+
+        Label::parse(pattern).map(|label| match label {
+            Label::Wildcard => TargetPattern::Recursive(PathBuf::new()),
+            label => TargetPattern::Target(label),
+        })
+    }
+
+    /// Whether this pattern selects the target with this label, or excludes
+    /// it if this is an exclusion.
+    pub fn matches(&self, label: &Label) -> bool {
+        match (self, label) {
+            (TargetPattern::Exclude(pattern), _) => pattern.matches(label),
+            (TargetPattern::Target(target), _) => target == label,
+            (TargetPattern::Recursive(root), _) if root.as_os_str().is_empty() => true,
+            (TargetPattern::Recursive(root), Label::Absolute { path, .. }) => {
+                Path::new(path).starts_with(root)
+            }
+            (TargetPattern::Package(package), Label::Absolute { path, .. }) => path == package,
+            _ => false,
+        }
+    }
+
+    pub fn is_exclusion(&self) -> bool {
+        matches!(self, TargetPattern::Exclude(_))
+    }
+
+    /// Select the labels matched by these patterns, sorted.
+    pub fn select<'a, I>(patterns: &[TargetPattern], labels: I) -> Vec<Label>
+    where
+        I: IntoIterator<Item = &'a Label>,
+    {
+        let mut selected: Vec<Label> = labels
+            .into_iter()
+            .filter(|label| {
+                patterns.iter().fold(false, |selected, pattern| {
+                    if !pattern.matches(label) {
+                        selected
+                    } else {
+                        !pattern.is_exclusion()
+                    }
+                })
+            })
+            .cloned()
+            .collect();
+        selected.sort_by_key(|label| label.to_string());
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(pattern: &str) -> TargetPattern {
+        TargetPattern::parse(pattern).unwrap()
+    }
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!(TargetPattern::Recursive(PathBuf::new()), parse("//..."));
+        assert_eq!(
+            TargetPattern::Recursive(PathBuf::from("a/b")),
+            parse("//a/b/...")
+        );
+        assert_eq!(
+            TargetPattern::Package(PathBuf::from("a/b")),
+            parse("//a/b:all")
+        );
+        assert_eq!(TargetPattern::Package(PathBuf::from("a")), parse("//a:*"));
+        assert_eq!(TargetPattern::Package(PathBuf::new()), parse("//:all"));
+        assert_eq!(TargetPattern::Target(Label::new("//a:b")), parse("//a:b"));
+        assert_eq!(
+            TargetPattern::Exclude(Box::new(TargetPattern::Recursive(PathBuf::from("a")))),
+            parse("-//a/...")
+        );
+
+        for pattern in &["//...", "//a/b/...", "//a:all", "//a:b", "-//a/..."] {
+            assert_eq!(*pattern, parse(pattern).to_string());
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!(TargetPattern::parse("//a...").is_err());
+        assert!(TargetPattern::parse("//a//...").is_err());
+        assert!(TargetPattern::parse("//../...").is_err());
+        assert!(TargetPattern::parse("--//a/...").is_err());
+        assert!(TargetPattern::parse("-").is_err());
+        assert!(TargetPattern::parse("//a b:all").is_err());
+    }
+
+    #[test]
+    fn selects_targets_in_order() {
+        let labels: Vec<Label> = vec![
+            Label::new("//a:x"),
+            Label::new("//a/b:y"),
+            Label::new("//a/b/c:z"),
+            Label::new("//ab:w"),
+            Label::new("//d:v"),
+        ];
+        let select = |patterns: &[&str]| -> Vec<String> {
+            let patterns: Vec<TargetPattern> = patterns.iter().map(|p| parse(p)).collect();
+            TargetPattern::select(&patterns, &labels)
+                .iter()
+                .map(|label| label.to_string())
+                .collect()
+        };
+
+        assert_eq!(vec!["//a/b/c:z", "//a/b:y", "//a:x"], select(&["//a/..."]));
+        assert_eq!(vec!["//a/b:y"], select(&["//a/b:all"]));
+        assert_eq!(vec!["//a:x"], select(&["//a/...", "-//a/b/..."]));
+        assert_eq!(
+            vec!["//a/b/c:z", "//a/b:y", "//a:x", "//ab:w", "//d:v"],
+            select(&["-//a/...", "//..."])
+        );
+        assert_eq!(vec!["//a/b:y", "//d:v"], select(&["//d:v", "//a/b:*"]));
+        assert!(select(&["-//..."]).is_empty());
+    }
+}
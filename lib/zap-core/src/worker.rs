@@ -4,6 +4,7 @@ use dashmap::DashMap;
 use log::*;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::path::PathBuf;
 use std::sync::{Arc, RwLock};
 use zap_buildscript::*;
@@ -36,16 +37,37 @@ impl ZapWorker {
     }
 
     pub async fn load(&mut self, root: &PathBuf) -> Result<(), anyhow::Error> {
-        self.scan(&root)?;
-        self.configure_bs_ctx()?;
-        self.load_default_toolchains().await?;
-        self.load_default_rules().await?;
-        self.load_local_toolchains().await?;
-        self.load_local_rules().await?;
+        let profiler = self.config.profiler.clone();
+        let _span = profiler.span("load", "load");
+        {
+            let _span = profiler.span("load", "scan");
+            self.scan(root)?;
+        }
+        {
+            let _span = profiler.span("load", "configure_buildscript");
+            self.configure_bs_ctx()?;
+        }
+        {
+            let _span = profiler.span("load", "load_default_toolchains");
+            self.load_default_toolchains().await?;
+        }
+        {
+            let _span = profiler.span("load", "load_default_rules");
+            self.load_default_rules().await?;
+        }
+        {
+            let _span = profiler.span("load", "load_local_toolchains");
+            self.load_local_toolchains().await?;
+        }
+        {
+            let _span = profiler.span("load", "load_local_rules");
+            self.load_local_rules().await?;
+        }
         Ok(())
     }
 
     pub fn build_dep_graph(&mut self) -> Result<(), anyhow::Error> {
+        let _span = self.config.profiler.span("load", "build_dep_graph");
         WorkspaceScanner::collect_targets(
             &mut self.workspace,
             &(*self.rule_manager).read().unwrap(),
@@ -293,11 +315,48 @@ impl ZapWorker {
                     .and_then(|val| val.as_str())
                     .map(|cwd| PathBuf::from(cwd.to_string()));
 
+                let timeout: Option<std::time::Duration> = match obj.get("timeout") {
+                    None | Some(Value::Null) => None,
+                    Some(timeout) => Some(
+                        timeout
+                            .as_f64()
+                            .filter(|secs| *secs > 0.0)
+                            .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+                            .context(format!(
+                                "Expected the timeout of an action of {} to be a positive number of seconds, instead found: {}",
+                                label.to_string(),
+                                timeout
+                            ))?,
+                    ),
+                };
+
                 let mut action = Action::exec(cmd);
                 action.args(&args);
                 if let Some(cwd) = cwd {
                     action.cwd(&cwd);
                 }
+                if let Some(timeout) = timeout {
+                    action.timeout(timeout);
+                }
+                let env = match obj.get("env") {
+                    None | Some(Value::Null) => None,
+                    Some(env) => Some(env.as_object().context(format!(
+                        "Expected the env of an action of {} to be an object, instead found: {}",
+                        label.to_string(),
+                        env
+                    ))?),
+                };
+                if let Some(env) = env {
+                    for (key, value) in env {
+                        let value = value.as_str().context(format!(
+                            "Expected the env variable {:?} of an action of {} to be a string, instead found: {}",
+                            key,
+                            label.to_string(),
+                            value
+                        ))?;
+                        action.env(key, value);
+                    }
+                }
                 let action = action.build();
 
                 let new_actions = if let Some(entry) = action_map.get(&label) {
@@ -371,10 +430,16 @@ impl ZapWorker {
                         "label" => Ok(CfgValueType::Label),
                         "file" => Ok(CfgValueType::File),
                         "string" => Ok(CfgValueType::String),
+                        "bool" => Ok(CfgValueType::Bool),
+                        "int" => Ok(CfgValueType::Int),
+                        "string_dict" => Ok(CfgValueType::StringDict),
+                        "label_keyed_dict" => Ok(CfgValueType::LabelKeyedDict),
                         "list_of_label" => Ok(CfgValueType::List(Box::new(CfgValueType::Label))),
                         "list_of_file" => Ok(CfgValueType::List(Box::new(CfgValueType::File))),
                         "list_of_string" => Ok(CfgValueType::List(Box::new(CfgValueType::String))),
-                        _ => Err(anyhow!("Unrecognized rule config key type {} -- valid types are  label(), file(), string(), and their array variants", t.to_string())),
+                        "list_of_bool" => Ok(CfgValueType::List(Box::new(CfgValueType::Bool))),
+                        "list_of_int" => Ok(CfgValueType::List(Box::new(CfgValueType::Int))),
+                        _ => Err(anyhow!("Unrecognized rule config key type {} -- valid types are  label(), file(), string(), bool(), int(), their array variants, and dict() or dict(label())", t.to_string())),
                     }?;
 
             cfg.insert(k.to_string(), value_type);
@@ -390,7 +455,8 @@ impl ZapWorker {
             let t = config
                 .get(k)
                 .context(format!("Could not find type for key {:?}", k))?;
-            let typed_value = (v.clone(), t.clone()).into();
+            let typed_value = CfgValue::try_from((v.clone(), t.clone()))
+                .context(format!("Invalid default value for key {:?}", k))?;
             default_cfg.insert(k.to_string(), typed_value);
         }
         let defaults = RuleConfig(default_cfg);
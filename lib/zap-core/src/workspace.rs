@@ -14,6 +14,7 @@ pub struct Workspace {
     pub local_zap_root: PathBuf,
     pub workspace_root: PathBuf,
     targets: Vec<Target>,
+    exec_policy: ExecPolicy,
 }
 
 impl Workspace {
@@ -34,6 +35,7 @@ impl Workspace {
             name,
             targets: vec![],
             workspace_root,
+            exec_policy: ExecPolicy::default(),
         };
 
         workspace.ensure_dirs()?;
@@ -82,6 +84,18 @@ impl Workspace {
         self
     }
 
+    pub fn with_exec_policy(self, exec_policy: ExecPolicy) -> Workspace {
+        Workspace {
+            exec_policy,
+            ..self
+        }
+    }
+
+    /// How every action in this workspace is run.
+    pub fn exec_policy(&self) -> &ExecPolicy {
+        &self.exec_policy
+    }
+
     pub fn targets(&self) -> &[Target] {
         &self.targets
     }
@@ -109,4 +123,14 @@ impl Workspace {
     pub fn outputs_root(&self) -> &PathBuf {
         &self.local_outputs_root
     }
+
+    /// Where the logs of every action run to build a target are kept.
+    pub fn logs_root(&self) -> PathBuf {
+        self.local_zap_root.join("logs")
+    }
+
+    /// Where the duration of the last build of every target is kept.
+    pub fn timings_path(&self) -> PathBuf {
+        self.local_zap_root.join("timings")
+    }
 }